@@ -0,0 +1,130 @@
+// Copyright 2019-2021 Manta Network.
+// This file is part of manta-crypto.
+//
+// manta-crypto is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// manta-crypto is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with manta-crypto.  If not, see <http://www.gnu.org/licenses/>.
+
+//! This file implements the string encoding of shielded addresses.
+//! An address bundles the keys a payer needs, and is encoded with bech32m:
+//! ```text
+//! hrp | 1 | base32(version | k | ecies_pk) | checksum
+//! ```
+//! The human-readable part names the network. Addresses are longer than
+//! the 90 characters of BIP-173, so the length limit is not enforced.
+use ark_std::{string::String, vec::Vec};
+use bech32::{FromBase32, ToBase32, Variant};
+
+/// The version of the address payload.
+pub const ADDRESS_VERSION: u8 = 0;
+/// The length of the address payload: version (1) | k (32) | ecies_pk (32).
+const ADDRESS_PAYLOAD_BYTES: usize = 1 + 32 + 32;
+
+/// The networks that an address can be used on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Network {
+	Manta,
+	Calamari,
+	Dolphin,
+}
+
+impl Network {
+	/// The human-readable prefix of the addresses of the network.
+	pub fn hrp(&self) -> &'static str {
+		match self {
+			Network::Manta => "manta",
+			Network::Calamari => "calamari",
+			Network::Dolphin => "dolphin",
+		}
+	}
+
+	/// The network of a human-readable prefix.
+	pub fn from_hrp(hrp: &str) -> Option<Self> {
+		[Network::Manta, Network::Calamari, Network::Dolphin]
+			.iter()
+			.find(|network| network.hrp() == hrp)
+			.copied()
+	}
+}
+
+/// Errors of address parsing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressError {
+	/// The string is not valid bech32, e.g., the checksum does not match.
+	Encoding(bech32::Error),
+	/// The checksum is of the original bech32 variant rather than bech32m.
+	InvalidVariant,
+	/// The human-readable prefix does not name a network.
+	UnknownNetwork,
+	/// The payload has a wrong length.
+	InvalidLength,
+	/// The payload version is not supported.
+	UnsupportedVersion(u8),
+}
+
+impl From<bech32::Error> for AddressError {
+	fn from(e: bech32::Error) -> Self {
+		AddressError::Encoding(e)
+	}
+}
+
+impl core::fmt::Display for AddressError {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		core::fmt::Debug::fmt(self, f)
+	}
+}
+
+impl ark_std::error::Error for AddressError {}
+
+/// A shielded address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShieldedAddress {
+	/// the commitment public key of the receiver
+	pub k: [u8; 32],
+	/// the `Ecies` public key of the receiver
+	pub ecies_pk: [u8; 32],
+}
+
+impl ShieldedAddress {
+	/// Format the address for `network`.
+	pub fn format(&self, network: Network) -> String {
+		let mut payload = Vec::with_capacity(ADDRESS_PAYLOAD_BYTES);
+		payload.push(ADDRESS_VERSION);
+		payload.extend_from_slice(&self.k);
+		payload.extend_from_slice(&self.ecies_pk);
+		bech32::encode(network.hrp(), payload.to_base32(), Variant::Bech32m)
+			.expect("network prefixes are valid bech32 prefixes")
+	}
+
+	/// Parse an address, together with its network.
+	pub fn parse(address: &str) -> Result<(Network, Self), AddressError> {
+		let (hrp, data, variant) = bech32::decode(address)?;
+		if variant != Variant::Bech32m {
+			return Err(AddressError::InvalidVariant);
+		}
+		let network = Network::from_hrp(&hrp).ok_or(AddressError::UnknownNetwork)?;
+
+		let payload = Vec::<u8>::from_base32(&data)?;
+		if payload.len() != ADDRESS_PAYLOAD_BYTES {
+			return Err(AddressError::InvalidLength);
+		}
+		if payload[0] != ADDRESS_VERSION {
+			return Err(AddressError::UnsupportedVersion(payload[0]));
+		}
+
+		let mut k = [0u8; 32];
+		k.copy_from_slice(&payload[1..33]);
+		let mut ecies_pk = [0u8; 32];
+		ecies_pk.copy_from_slice(&payload[33..65]);
+		Ok((network, Self { k, ecies_pk }))
+	}
+}
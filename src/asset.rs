@@ -0,0 +1,107 @@
+// Copyright 2019-2021 Manta Network.
+// This file is part of manta-crypto.
+//
+// manta-crypto is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// manta-crypto is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with manta-crypto.  If not, see <http://www.gnu.org/licenses/>.
+
+//! This file implements asset-aware coin commitments.
+//! The asset identifier is bound into the value commitment
+//! `cm = com(asset_id | value | k, s)`, so that a single shielded pool
+//! can hold several assets.
+use crate::*;
+use ark_crypto_primitives::CommitmentGadget;
+use ark_ed_on_bls12_381::Fq;
+use ark_r1cs_std::{bits::uint64::UInt64, prelude::*};
+use ark_relations::r1cs::SynthesisError;
+use ark_std::vec::Vec;
+
+/// The identifier of an asset.
+pub type AssetId = u64;
+
+/// The input of an asset-aware value commitment: `asset_id | value | k`,
+/// with `asset_id` and `value` in little-endian.
+pub fn asset_commitment_input(asset_id: AssetId, value: u64, k: &[u8; 32]) -> Vec<u8> {
+	[
+		asset_id.to_le_bytes().as_ref(),
+		value.to_le_bytes().as_ref(),
+		k.as_ref(),
+	]
+	.concat()
+}
+
+pub trait AssetCommitment {
+	type Param;
+	type Randomness;
+	type Commitment;
+	type Error;
+
+	/// generate a value commitment that binds the asset identifier
+	fn commit_asset(
+		param: &Self::Param,
+		asset_id: AssetId,
+		value: u64,
+		k: &[u8; 32],
+		randomness: &Self::Randomness,
+	) -> Result<Self::Commitment, Self::Error>;
+}
+
+impl AssetCommitment for MantaCrypto {
+	type Param = CommitmentParam;
+	type Randomness = SecretBytes;
+	type Commitment = [u8; 32];
+	type Error = CommitmentError;
+
+	fn commit_asset(
+		param: &Self::Param,
+		asset_id: AssetId,
+		value: u64,
+		k: &[u8; 32],
+		randomness: &Self::Randomness,
+	) -> Result<Self::Commitment, Self::Error> {
+		let input = asset_commitment_input(asset_id, value, k);
+		<Self as Commitment>::commit(param, &input, randomness)
+	}
+}
+
+//=======================
+// gadgets
+//=======================
+
+/// Gadget for `commit_asset`.
+pub fn asset_commitment_gadget(
+	param: &CommitmentParamVar,
+	asset_id: &UInt64<Fq>,
+	value: &UInt64<Fq>,
+	k: &[UInt8<Fq>],
+	randomness: &MantaCoinCommitmentOpenVar,
+) -> Result<MantaCoinCommitmentOutputVar, SynthesisError> {
+	let mut input = asset_id.to_bytes()?;
+	input.extend(value.to_bytes()?);
+	input.extend_from_slice(k);
+	CommitmentSchemeVar::commit(param, &input, randomness)
+}
+
+/// Enforce that the input and output coins of a transfer share a single asset identifier.
+/// With no inputs, e.g., a mint, the outputs still share a single asset identifier.
+pub fn enforce_same_asset(
+	inputs: &[UInt64<Fq>],
+	outputs: &[UInt64<Fq>],
+) -> Result<(), SynthesisError> {
+	let mut asset_ids = inputs.iter().chain(outputs);
+	if let Some(first) = asset_ids.next() {
+		for asset_id in asset_ids {
+			first.enforce_equal(asset_id)?;
+		}
+	}
+	Ok(())
+}
@@ -0,0 +1,162 @@
+// Copyright 2019-2021 Manta Network.
+// This file is part of manta-crypto.
+//
+// manta-crypto is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// manta-crypto is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with manta-crypto.  If not, see <http://www.gnu.org/licenses/>.
+
+//! This file implements the ledger tree as the node sees it: an incremental
+//! merkle tree, together with a window of its recent roots and a stack of
+//! checkpoints.
+//!
+//! A proof is generated against a root that may be a few blocks old, so the
+//! node accepts any root of the window. A chain reorganization undoes the
+//! appends of the orphaned blocks by rewinding to their checkpoints.
+//! The frontier of the tree is only O(HEIGHT) digests, so a checkpoint
+//! stores a copy of the frontier and of the window, and a rewind restores
+//! both exactly.
+use crate::{
+	incremental_merkle_tree::Frontier,
+	merkle_tree::{MerkleTreeError, TreeDigest, TreeHashing},
+	IncrementalMerkleTree,
+};
+use ark_crypto_primitives::FixedLengthCRH;
+use ark_ff::ToBytes;
+use ark_std::collections::VecDeque;
+
+/// An incremental merkle tree with a window of recent roots, and checkpoints.
+pub struct CheckpointedMerkleTree<P: TreeHashing> {
+	tree: IncrementalMerkleTree<P>,
+	/// the recorded roots, oldest first
+	roots: VecDeque<TreeDigest<P>>,
+	/// the maximum number of recorded roots
+	root_history_size: usize,
+	/// the saved states, oldest first
+	checkpoints: VecDeque<Checkpoint<P>>,
+	/// the maximum number of checkpoints
+	max_checkpoints: usize,
+}
+
+/// A saved state of a `CheckpointedMerkleTree`.
+struct Checkpoint<P: TreeHashing> {
+	frontier: Frontier<P>,
+	roots: VecDeque<TreeDigest<P>>,
+}
+
+impl<P: TreeHashing> CheckpointedMerkleTree<P> {
+	/// Build an empty tree, that keeps up to `root_history_size` recent roots,
+	/// and up to `max_checkpoints` checkpoints.
+	pub fn new(
+		param: <P::H as FixedLengthCRH>::Parameters,
+		root_history_size: usize,
+		max_checkpoints: usize,
+	) -> Result<Self, MerkleTreeError> {
+		Ok(Self {
+			tree: IncrementalMerkleTree::new(param)?,
+			roots: VecDeque::with_capacity(root_history_size),
+			root_history_size,
+			checkpoints: VecDeque::new(),
+			max_checkpoints,
+		})
+	}
+
+	/// The current tree.
+	pub fn tree(&self) -> &IncrementalMerkleTree<P> {
+		&self.tree
+	}
+
+	/// Append a leaf to the tree, and return its index.
+	pub fn append<L: ToBytes>(&mut self, leaf: &L) -> Result<usize, MerkleTreeError> {
+		self.tree.append(leaf)
+	}
+
+	/// The root of the tree.
+	pub fn root(&self) -> Result<TreeDigest<P>, MerkleTreeError> {
+		self.tree.root()
+	}
+
+	/// Record the current root in the window, e.g., at the end of a block,
+	/// and evict the oldest root once the window is full.
+	pub fn record_root(&mut self) -> Result<TreeDigest<P>, MerkleTreeError> {
+		let root = self.tree.root()?;
+		if self.root_history_size == 0 {
+			return Ok(root);
+		}
+		if self.roots.len() == self.root_history_size {
+			self.roots.pop_front();
+		}
+		self.roots.push_back(root.clone());
+		Ok(root)
+	}
+
+	/// Whether the root is one of the recorded roots of the window.
+	pub fn is_recent_root(&self, root: &TreeDigest<P>) -> bool {
+		self.roots.iter().any(|recent| recent == root)
+	}
+
+	/// The recorded roots, oldest first.
+	pub fn recent_roots(&self) -> impl Iterator<Item = &TreeDigest<P>> + '_ {
+		self.roots.iter()
+	}
+
+	/// Save the current state, and drop the oldest checkpoint once there
+	/// are `max_checkpoints` of them.
+	pub fn checkpoint(&mut self) {
+		if self.max_checkpoints == 0 {
+			return;
+		}
+		if self.checkpoints.len() == self.max_checkpoints {
+			self.checkpoints.pop_front();
+		}
+		self.checkpoints.push_back(Checkpoint {
+			frontier: self.tree.frontier.clone(),
+			roots: self.roots.clone(),
+		});
+	}
+
+	/// The number of checkpoints that can be rewound to.
+	pub fn checkpoint_count(&self) -> usize {
+		self.checkpoints.len()
+	}
+
+	/// Restore the state of the latest checkpoint, and remove the checkpoint.
+	pub fn rewind(&mut self) -> Result<(), MerkleTreeError> {
+		let checkpoint = self
+			.checkpoints
+			.pop_back()
+			.ok_or(MerkleTreeError::NoCheckpoint)?;
+		self.tree.frontier = checkpoint.frontier;
+		self.roots = checkpoint.roots;
+		Ok(())
+	}
+}
+
+impl<P: TreeHashing> Clone for CheckpointedMerkleTree<P> {
+	fn clone(&self) -> Self {
+		Self {
+			tree: self.tree.clone(),
+			roots: self.roots.clone(),
+			root_history_size: self.root_history_size,
+			checkpoints: self.checkpoints.clone(),
+			max_checkpoints: self.max_checkpoints,
+		}
+	}
+}
+
+impl<P: TreeHashing> Clone for Checkpoint<P> {
+	fn clone(&self) -> Self {
+		Self {
+			frontier: self.frontier.clone(),
+			roots: self.roots.clone(),
+		}
+	}
+}
@@ -14,27 +14,50 @@
 // You should have received a copy of the GNU General Public License
 // along with manta-crypto.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::*;
+use crate::{
+	param::{PERDERSON_WINDOW_NUM, PERDERSON_WINDOW_SIZE},
+	*,
+};
 use ark_crypto_primitives::{
 	commitment::pedersen::Randomness, CommitmentScheme as ArkCommitmentScheme,
 };
 use ark_ed_on_bls12_381::Fr;
 use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
 use ark_std::vec::Vec;
-use manta_error::MantaError;
+use subtle::ConstantTimeEq;
+
+/// Errors of the commitment scheme.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommitmentError {
+	/// The randomness is not a canonical encoding of a scalar.
+	MalformedRandomness,
+	/// The input is longer than the Perdersen windows can hold.
+	OversizeInput,
+	/// The commitment cannot be serialized.
+	SerializationFailure,
+}
+
+impl core::fmt::Display for CommitmentError {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		core::fmt::Debug::fmt(self, f)
+	}
+}
+
+impl ark_std::error::Error for CommitmentError {}
 
 pub trait Commitment {
 	type Param;
 	type Input;
 	type Randomness;
 	type Commitment;
+	type Error;
 
 	/// generate a commitment for some given input
 	fn commit(
 		param: &Self::Param,
 		input: &Self::Input,
 		randomness: &Self::Randomness,
-	) -> Result<Self::Commitment, MantaError>;
+	) -> Result<Self::Commitment, Self::Error>;
 
 	/// check the commitment is correct for some given input
 	fn check_commitment(
@@ -42,33 +65,46 @@ pub trait Commitment {
 		input: &Self::Input,
 		randomness: &Self::Randomness,
 		commitment: &Self::Commitment,
-	) -> Result<bool, MantaError>;
+	) -> Result<bool, Self::Error>;
 }
 
 impl Commitment for MantaCrypto {
 	type Param = CommitmentParam;
 	type Input = Vec<u8>;
-	type Randomness = [u8; 32];
+	type Randomness = SecretBytes;
 	type Commitment = [u8; 32];
+	type Error = CommitmentError;
 
 	fn commit(
 		param: &Self::Param,
 		input: &Self::Input,
 		randomness: &Self::Randomness,
-	) -> Result<Self::Commitment, MantaError> {
-		let open = Randomness(Fr::deserialize(randomness.as_ref())?);
-		let commit = CommitmentScheme::commit(param, input, &open)?;
+	) -> Result<Self::Commitment, Self::Error> {
+		if input.len() * 8 > PERDERSON_WINDOW_SIZE * PERDERSON_WINDOW_NUM {
+			return Err(CommitmentError::OversizeInput);
+		}
+		let open = Randomness(
+			Fr::deserialize(randomness.expose().as_ref())
+				.map_err(|_| CommitmentError::MalformedRandomness)?,
+		);
+		// the input length is the only failure case of the Perdersen commitment
+		let commit = CommitmentScheme::commit(param, input, &open)
+			.map_err(|_| CommitmentError::OversizeInput)?;
 		let mut commit_bytes = [0u8; 32];
-		commit.serialize(commit_bytes.as_mut())?;
+		commit
+			.serialize(commit_bytes.as_mut())
+			.map_err(|_| CommitmentError::SerializationFailure)?;
 		Ok(commit_bytes)
 	}
 
+	/// The recomputed commitment is compared in constant time.
 	fn check_commitment(
 		param: &Self::Param,
 		input: &Self::Input,
 		randomness: &Self::Randomness,
 		commitment: &Self::Commitment,
-	) -> Result<bool, MantaError> {
-		Ok(Self::commit(param, input, &randomness)? == *commitment)
+	) -> Result<bool, Self::Error> {
+		let expected = Self::commit(param, input, randomness)?;
+		Ok(expected[..].ct_eq(&commitment[..]).into())
 	}
 }
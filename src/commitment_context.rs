@@ -0,0 +1,130 @@
+// Copyright 2019-2021 Manta Network.
+// This file is part of manta-crypto.
+//
+// manta-crypto is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// manta-crypto is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with manta-crypto.  If not, see <http://www.gnu.org/licenses/>.
+
+//! This file implements Perdersen commitments with precomputed window tables.
+//! The commitments are identical to the ones of `<MantaCrypto as Commitment>`.
+use crate::{
+	param::{PERDERSON_WINDOW_NUM, PERDERSON_WINDOW_SIZE},
+	CommitmentError, CommitmentParam, SecretBytes,
+};
+use ark_ec::ProjectiveCurve;
+use ark_ed_on_bls12_381::{EdwardsAffine, EdwardsProjective, Fr};
+use ark_ff::Zero;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use ark_std::{vec, vec::Vec};
+#[cfg(feature = "std")]
+use rayon::prelude::*;
+
+/// A commitment context holds, for each window of the Perdersen commitment,
+/// the sums of the window's generators for every possible window value.
+/// A commitment then costs a single addition per window.
+pub struct CommitmentContext {
+	/// tables for the windows of the input
+	input_tables: Vec<Vec<EdwardsAffine>>,
+	/// tables for the windows of the randomness
+	randomness_tables: Vec<Vec<EdwardsAffine>>,
+}
+
+impl CommitmentContext {
+	/// Precompute the window tables of `param`.
+	pub fn new(param: &CommitmentParam) -> Self {
+		let input_tables = param
+			.generators
+			.iter()
+			.map(|generators| window_table(generators))
+			.collect();
+		let randomness_tables = param
+			.randomness_generator
+			.chunks(PERDERSON_WINDOW_SIZE)
+			.map(window_table)
+			.collect();
+
+		Self {
+			input_tables,
+			randomness_tables,
+		}
+	}
+
+	/// Generate a commitment for `input` with `randomness`.
+	pub fn commit(
+		&self,
+		input: &[u8],
+		randomness: &SecretBytes,
+	) -> Result<[u8; 32], CommitmentError> {
+		if input.len() * 8 > PERDERSON_WINDOW_SIZE * PERDERSON_WINDOW_NUM {
+			return Err(CommitmentError::OversizeInput);
+		}
+		// the randomness bits are read from its bytes directly,
+		// so only canonical encodings are accepted
+		Fr::deserialize(randomness.expose().as_ref())
+			.map_err(|_| CommitmentError::MalformedRandomness)?;
+
+		let mut commit = EdwardsProjective::zero();
+		accumulate(&self.input_tables, input, &mut commit);
+		accumulate(&self.randomness_tables, randomness.expose(), &mut commit);
+
+		let mut commit_bytes = [0u8; 32];
+		commit
+			.into_affine()
+			.serialize(commit_bytes.as_mut())
+			.map_err(|_| CommitmentError::SerializationFailure)?;
+		Ok(commit_bytes)
+	}
+
+	/// Generate commitments for a batch of `(input, randomness)` pairs.
+	/// With the `std` feature, the batch is committed across threads.
+	pub fn commit_batch(
+		&self,
+		batch: &[(Vec<u8>, SecretBytes)],
+	) -> Result<Vec<[u8; 32]>, CommitmentError> {
+		#[cfg(feature = "std")]
+		let iter = batch.par_iter();
+		#[cfg(not(feature = "std"))]
+		let iter = batch.iter();
+
+		iter.map(|(input, randomness)| self.commit(input, randomness))
+			.collect()
+	}
+}
+
+/// The table of a window: entry `k` is the sum of the generators
+/// that are selected by the bits of `k`.
+fn window_table(generators: &[EdwardsProjective]) -> Vec<EdwardsAffine> {
+	let mut table = vec![EdwardsProjective::zero(); 1 << generators.len()];
+	for k in 1..table.len() {
+		// add the generator of the lowest set bit to the entry without it
+		table[k] = table[k & (k - 1)] + generators[k.trailing_zeros() as usize];
+	}
+	EdwardsProjective::batch_normalization_into_affine(&table)
+}
+
+/// Add the table entries selected by the windows of `bytes` to `acc`.
+/// The bits of `bytes` are read in little-endian order, and missing bytes are zeros,
+/// which matches the padding of the Perdersen commitment.
+fn accumulate(tables: &[Vec<EdwardsAffine>], bytes: &[u8], acc: &mut EdwardsProjective) {
+	for (window, table) in tables.iter().enumerate() {
+		let index = (0..PERDERSON_WINDOW_SIZE).fold(0usize, |index, j| {
+			let bit = window * PERDERSON_WINDOW_SIZE + j;
+			let set = bytes
+				.get(bit / 8)
+				.map_or(false, |byte| (byte >> (bit % 8)) & 1 == 1);
+			index | ((set as usize) << j)
+		});
+		if index != 0 {
+			acc.add_assign_mixed(&table[index]);
+		}
+	}
+}
@@ -21,18 +21,61 @@
 //! This file implements Diffie-Hellman Key Agreement for value encryption
 //! TODO: maybe we should simply use ecies crate
 //! <https://github.com/phayes/ecies-ed25519/>
-use crate::MantaCrypto;
-use aes::{cipher::NewBlockCipher, Aes256, BlockDecrypt, BlockEncrypt};
+#[cfg(feature = "pq-hybrid")]
+use crate::Secret;
+use crate::{MantaCrypto, SecretBytes};
+use aes::{cipher::NewBlockCipher, Aes256, BlockDecrypt};
+use aes_gcm::{
+	aead::{AeadInPlace, NewAead},
+	Aes256Gcm, Key, Nonce, Tag,
+};
 use ark_std::rand::{CryptoRng, RngCore};
 use blake2::{Blake2s, Digest};
 use generic_array::GenericArray;
+use hkdf::Hkdf;
 use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
 
+/// The AES-GCM nonce.
+/// Every message is encrypted under a fresh key that is derived from an
+/// ephemeral Diffie-Hellman exchange, so a fixed nonce is never reused with a key.
+const ECIES_NONCE: [u8; 12] = [0u8; 12];
+/// The KDF context of value encryption.
+const ECIES_KDF_CONTEXT: &[u8] = b"manta value ecies";
+
+/// The salt of the HKDF extract step.
+const MANTA_KDF_SALT: &[u8] = b"manta kdf instantiated with blake2s hash function";
+/// The label of the encryption subkey.
+pub const MANTA_KDF_ENCRYPTION_LABEL: &[u8] = b"encryption key";
+/// The label of the tag subkey.
+pub const MANTA_KDF_TAG_LABEL: &[u8] = b"tag key";
+
+/// Errors of ECIES decryption.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EciesError {
+	/// The ciphertext was tampered with, or was not encrypted to this key.
+	AuthenticationFailure,
+	/// The ciphertext is too short for its wire layout.
+	MalformedCiphertext,
+	/// The ciphertext has a wire layout version that is not supported.
+	UnsupportedVersion(u8),
+	/// The decrypted plaintext cannot be parsed.
+	MalformedPlaintext,
+}
+
+impl core::fmt::Display for EciesError {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		core::fmt::Debug::fmt(self, f)
+	}
+}
+
+impl ark_std::error::Error for EciesError {}
+
 pub trait Ecies {
 	type PublicKey;
 	type PrivateKey;
 	type Message;
 	type Ciphertext;
+	type Error;
 
 	/// Genrate a pair of keys
 	fn keygen<R: RngCore + CryptoRng>(rng: &mut R) -> (Self::PublicKey, Self::PrivateKey);
@@ -45,20 +88,25 @@ pub trait Ecies {
 	) -> Self::Ciphertext;
 
 	/// Decrypt a ciphertext with a secret key.
-	fn decrypt(sk: &Self::PrivateKey, cipher: &Self::Ciphertext) -> Self::Message;
+	/// Fails if the ciphertext does not authenticate.
+	fn decrypt(
+		sk: &Self::PrivateKey,
+		cipher: &Self::Ciphertext,
+	) -> Result<Self::Message, Self::Error>;
 }
 
 impl Ecies for MantaCrypto {
 	type PublicKey = [u8; 32];
-	type PrivateKey = [u8; 32];
+	type PrivateKey = SecretBytes;
 	type Message = u64;
-	type Ciphertext = [u8; 48];
+	type Ciphertext = [u8; 56];
+	type Error = EciesError;
 
 	/// Generate a pair of keys
 	fn keygen<R: RngCore + CryptoRng>(rng: &mut R) -> (Self::PublicKey, Self::PrivateKey) {
 		let sk = StaticSecret::new(rng);
 		let pk = PublicKey::from(&sk);
-		(pk.to_bytes(), sk.to_bytes())
+		(pk.to_bytes(), SecretBytes::new(sk.to_bytes()))
 	}
 
 	/// Encrypt the message under the input public key.
@@ -68,9 +116,9 @@ impl Ecies for MantaCrypto {
 	///     1. sample a random, ephemeral field element: ephemeral_sk
 	///     2. compute the group element ephemeral_pk
 	///     3. compute the shared secret ss = pk^ephemeral_sk
-	///     4. set aes_key = KDF("manta kdf instantiated with blake2s hash function" | ss)
-	///     5. compute c = aes_enc(message.to_le_bytes(), aes_key)
-	///     6. return [c | pk]
+	///     4. set aes_key = KDF(ss, ephemeral_pk, pk).derive("encryption key")
+	///     5. compute (c, tag) = aes_gcm_enc(message.to_le_bytes(), aad = ephemeral_pk, aes_key)
+	///     6. return [c | tag | ephemeral_pk]
 	/// # </weight>
 	fn encrypt<R: RngCore + CryptoRng>(
 		pk: &Self::PublicKey,
@@ -80,20 +128,29 @@ impl Ecies for MantaCrypto {
 		let ephemeral_sk = EphemeralSecret::new(rng);
 		let ephemeral_pk = PublicKey::from(&ephemeral_sk);
 
-		let pk = PublicKey::from(*pk);
-		let shared_secret = ephemeral_sk.diffie_hellman(&pk);
-		let ss = manta_kdf(&shared_secret.to_bytes());
-		let aes_key = GenericArray::from_slice(&ss);
+		let shared_secret = ephemeral_sk.diffie_hellman(&PublicKey::from(*pk));
+		let kdf = MantaKdf::new(
+			ECIES_KDF_CONTEXT,
+			shared_secret.as_bytes(),
+			ephemeral_pk.as_bytes(),
+			pk,
+		);
+		let aes_key = kdf.derive(MANTA_KDF_ENCRYPTION_LABEL);
+		let cipher = Aes256Gcm::new(Key::from_slice(aes_key.expose()));
 
-		let msg = [message.to_le_bytes().as_ref(), [0u8; 8].as_ref()].concat();
-		assert_eq!(msg.len(), 16);
-		let mut block = GenericArray::clone_from_slice(&msg);
-		let cipher = Aes256::new(&aes_key);
-		cipher.encrypt_block(&mut block);
+		let mut block = message.to_le_bytes();
+		let tag = cipher
+			.encrypt_in_place_detached(
+				Nonce::from_slice(&ECIES_NONCE),
+				ephemeral_pk.as_bytes(),
+				&mut block,
+			)
+			.expect("an 8 bytes message never exceeds the AES-GCM length limit");
 
-		let mut res = [0u8; 48];
-		res[0..16].copy_from_slice(block.as_slice());
-		res[16..48].copy_from_slice(ephemeral_pk.to_bytes().as_ref());
+		let mut res = [0u8; 56];
+		res[0..8].copy_from_slice(block.as_ref());
+		res[8..24].copy_from_slice(tag.as_slice());
+		res[24..56].copy_from_slice(ephemeral_pk.as_bytes());
 		res
 	}
 
@@ -101,47 +158,169 @@ impl Ecies for MantaCrypto {
 	///
 	/// # <weight>
 	/// Steps:
-	///     1. parse cipher as [c | pk]
-	///     2. compute the shared secret ss = pk^sk
-	///     3. set aes_key = KDF("manta kdf instantiated with blake2s hash function" | ss)
-	///     4. compute m = aes_dec(cipher, aes_key)
+	///     1. parse cipher as [c | tag | ephemeral_pk]
+	///     2. compute the shared secret ss = ephemeral_pk^sk
+	///     3. set aes_key = KDF(ss, ephemeral_pk, pk).derive("encryption key"), with pk = g^sk
+	///     4. compute m = aes_gcm_dec(c, tag, aad = ephemeral_pk, aes_key), or fail
 	///     5. return m as u64
 	/// # </weight>
-	fn decrypt(sk: &Self::PrivateKey, cipher: &Self::Ciphertext) -> Self::Message {
-		let sk = StaticSecret::from(*sk);
+	fn decrypt(
+		sk: &Self::PrivateKey,
+		cipher: &Self::Ciphertext,
+	) -> Result<Self::Message, Self::Error> {
+		let sk = StaticSecret::from(*sk.expose());
 
 		let mut pk_bytes = [0u8; 32];
-		pk_bytes.copy_from_slice(cipher[16..48].as_ref());
-		let pk = PublicKey::from(pk_bytes);
-
-		let shared_secret = sk.diffie_hellman(&pk);
-		let ss = manta_kdf(&shared_secret.to_bytes());
-		let aes_key = GenericArray::from_slice(&ss);
-		let mut block = [0u8; 16];
-		block.copy_from_slice(cipher[0..16].as_ref());
-		let mut block = GenericArray::from_mut_slice(&mut block);
-		let cipher = Aes256::new(&aes_key);
-		cipher.decrypt_block(&mut block);
-
-		(block[0] as u64)
-			+ ((block[1] as u64) << 8)
-			+ ((block[2] as u64) << 16)
-			+ ((block[3] as u64) << 24)
-			+ ((block[4] as u64) << 32)
-			+ ((block[5] as u64) << 40)
-			+ ((block[6] as u64) << 48)
-			+ ((block[7] as u64) << 56)
+		pk_bytes.copy_from_slice(cipher[24..56].as_ref());
+		let ephemeral_pk = PublicKey::from(pk_bytes);
+
+		let pk = PublicKey::from(&sk);
+		let shared_secret = sk.diffie_hellman(&ephemeral_pk);
+		let kdf = MantaKdf::new(
+			ECIES_KDF_CONTEXT,
+			shared_secret.as_bytes(),
+			ephemeral_pk.as_bytes(),
+			pk.as_bytes(),
+		);
+		let aes_key = kdf.derive(MANTA_KDF_ENCRYPTION_LABEL);
+		let aes_gcm = Aes256Gcm::new(Key::from_slice(aes_key.expose()));
+
+		let mut block = [0u8; 8];
+		block.copy_from_slice(cipher[0..8].as_ref());
+		aes_gcm
+			.decrypt_in_place_detached(
+				Nonce::from_slice(&ECIES_NONCE),
+				ephemeral_pk.as_bytes(),
+				&mut block,
+				Tag::from_slice(&cipher[8..24]),
+			)
+			.map_err(|_| EciesError::AuthenticationFailure)?;
+
+		Ok(u64::from_le_bytes(block))
+	}
+}
+
+/// Decrypt a ciphertext of the legacy, unauthenticated 48 bytes format
+/// `[aes_enc(message) | ephemeral_pk]` with a secret key.
+///
+/// __Warning__: the legacy format has no MAC, so a tampered ciphertext
+/// decrypts to a garbage value instead of failing.
+pub fn decrypt_legacy(sk: &SecretBytes, cipher: &[u8; 48]) -> u64 {
+	let sk = StaticSecret::from(*sk.expose());
+
+	let mut pk_bytes = [0u8; 32];
+	pk_bytes.copy_from_slice(cipher[16..48].as_ref());
+	let pk = PublicKey::from(pk_bytes);
+
+	let shared_secret = sk.diffie_hellman(&pk);
+	let ss = legacy_kdf(shared_secret.as_bytes());
+	let aes_key = GenericArray::from_slice(ss.expose());
+	let mut block = [0u8; 16];
+	block.copy_from_slice(cipher[0..16].as_ref());
+	let mut block = GenericArray::from_mut_slice(&mut block);
+	let cipher = Aes256::new(&aes_key);
+	cipher.decrypt_block(&mut block);
+
+	(block[0] as u64)
+		+ ((block[1] as u64) << 8)
+		+ ((block[2] as u64) << 16)
+		+ ((block[3] as u64) << 24)
+		+ ((block[4] as u64) << 32)
+		+ ((block[5] as u64) << 40)
+		+ ((block[6] as u64) << 48)
+		+ ((block[7] as u64) << 56)
+}
+
+/// HKDF over Blake2s, bound to the transcript of an ECIES key exchange.
+///
+/// # <weight>
+/// Steps:
+///     1. extract: prk = hkdf-extract(salt = "manta kdf instantiated with blake2s hash function", ss)
+///     2. expand: subkey = hkdf-expand(prk, info = len(context) | context | ephemeral_pk | pk | label)
+/// # </weight>
+pub struct MantaKdf<'a> {
+	hkdf: Hkdf<Blake2s>,
+	context: &'a [u8],
+	ephemeral_pk: &'a [u8; 32],
+	pk: &'a [u8; 32],
+}
+
+impl<'a> MantaKdf<'a> {
+	/// Extract a pseudorandom key from the shared secret of an exchange
+	/// between `ephemeral_pk` and the recipient `pk`.
+	///
+	/// # Panics
+	/// Panics if `context` is longer than 255 bytes.
+	pub fn new(
+		context: &'a [u8],
+		shared_secret: &[u8; 32],
+		ephemeral_pk: &'a [u8; 32],
+		pk: &'a [u8; 32],
+	) -> Self {
+		assert!(context.len() <= u8::MAX as usize, "kdf context is too long");
+		Self {
+			hkdf: Hkdf::new(Some(MANTA_KDF_SALT), shared_secret),
+			context,
+			ephemeral_pk,
+			pk,
+		}
+	}
+
+	/// Extract a pseudorandom key from both the X25519 shared secret and
+	/// the shared secret of a post-quantum KEM, so that the subkeys stay secret
+	/// as long as either of the two exchanges is secure.
+	///
+	/// # Panics
+	/// Panics if `context` is longer than 255 bytes.
+	#[cfg(feature = "pq-hybrid")]
+	pub fn new_hybrid(
+		context: &'a [u8],
+		shared_secret: &[u8; 32],
+		kem_shared_secret: &[u8; 32],
+		ephemeral_pk: &'a [u8; 32],
+		pk: &'a [u8; 32],
+	) -> Self {
+		assert!(context.len() <= u8::MAX as usize, "kdf context is too long");
+		let mut ikm = Secret::new([0u8; 64]);
+		ikm.expose_mut()[0..32].copy_from_slice(shared_secret);
+		ikm.expose_mut()[32..64].copy_from_slice(kem_shared_secret);
+		Self {
+			hkdf: Hkdf::new(Some(MANTA_KDF_SALT), ikm.expose()),
+			context,
+			ephemeral_pk,
+			pk,
+		}
+	}
+
+	/// Expand the subkey of a given label.
+	pub fn derive(&self, label: &[u8]) -> SecretBytes {
+		let mut res = SecretBytes::new([0u8; 32]);
+		self.hkdf
+			.expand_multi_info(
+				&[
+					&[self.context.len() as u8],
+					self.context,
+					self.ephemeral_pk,
+					self.pk,
+					label,
+				],
+				res.expose_mut(),
+			)
+			.expect("32 bytes is a valid output length for hkdf");
+		res
 	}
 }
 
+// the kdf of the legacy format:
 // this function is a wrapper of blake2s: m = hkdf-extract(salt, seed)
 // with a fixed salt
-fn manta_kdf(input: &[u8]) -> [u8; 32] {
+fn legacy_kdf(input: &[u8]) -> SecretBytes {
 	let salt = "manta kdf instantiated with blake2s hash function";
 	let mut hasher = Blake2s::new();
-	hasher.update([input, salt.as_bytes()].concat());
+	hasher.update(input);
+	hasher.update(salt.as_bytes());
 	let digest = hasher.finalize();
-	let mut res = [0u8; 32];
-	res.copy_from_slice(digest.as_slice());
+	let mut res = SecretBytes::new([0u8; 32]);
+	res.expose_mut().copy_from_slice(digest.as_slice());
 	res
 }
@@ -0,0 +1,258 @@
+// Copyright 2019-2021 Manta Network.
+// This file is part of manta-crypto.
+//
+// manta-crypto is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// manta-crypto is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with manta-crypto.  If not, see <http://www.gnu.org/licenses/>.
+
+//! This file implements a post-quantum hybrid mode of note encryption.
+//! A note is encrypted under a key that is derived from both an X25519
+//! exchange and an ML-KEM-768 encapsulation, so that a ciphertext that is
+//! harvested today stays secret even if X25519 is broken later.
+//!
+//! The wire layout of a hybrid note ciphertext is
+//! ```text
+//! version (1) | ephemeral_pk (32) | kem_ciphertext (1088) | view_tag (4) | c | tag (16)
+//! ```
+//! where `c | tag` is sealed exactly like a `NoteEncryption` ciphertext.
+//!
+//! The ML-KEM randomness is sampled from the caller's rng and fed to the
+//! deterministic ML-KEM interfaces, so that every rng of this crate works.
+use crate::{
+	note_encryption::{
+		open_with_kdf, seal_with_kdf, NOTE_PLAINTEXT_HEADER_BYTES, TAG_BYTES, VIEW_TAG_BYTES,
+	},
+	Ecies, EciesError, MantaKdf, NotePlaintext, Secret, SecretBytes,
+};
+use ark_std::{
+	rand::{CryptoRng, RngCore},
+	vec::Vec,
+};
+use core::{convert::TryFrom, fmt};
+use ml_kem::{
+	kem::Decapsulate, Ciphertext, EncapsulateDeterministic, Encoded, EncodedSizeUser, KemCore,
+	MlKem768, B32,
+};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+/// The version of the hybrid note ciphertext wire layout.
+pub const HYBRID_NOTE_CIPHERTEXT_VERSION: u8 = 3;
+/// The length of an ML-KEM-768 encapsulation key.
+pub const HYBRID_KEM_PUBLIC_KEY_BYTES: usize = 1184;
+/// The length of an ML-KEM-768 ciphertext.
+const HYBRID_KEM_CIPHERTEXT_BYTES: usize = 1088;
+const HYBRID_HEADER_BYTES: usize = 1 + 32 + HYBRID_KEM_CIPHERTEXT_BYTES + VIEW_TAG_BYTES;
+
+/// The KDF context of hybrid note encryption.
+const HYBRID_KDF_CONTEXT: &[u8] = b"manta note hybrid ecies";
+
+type EncapsulationKey = <MlKem768 as KemCore>::EncapsulationKey;
+type DecapsulationKey = <MlKem768 as KemCore>::DecapsulationKey;
+
+/// The public key of hybrid note encryption.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HybridPublicKey {
+	/// the X25519 public key
+	pub x25519: [u8; 32],
+	/// the encoded ML-KEM-768 encapsulation key
+	pub ml_kem: [u8; HYBRID_KEM_PUBLIC_KEY_BYTES],
+}
+
+/// The private key of hybrid note encryption. The keys that decryption
+/// needs are derived once, when the private key is built from its seeds,
+/// rather than for every trial decryption.
+#[derive(Clone)]
+pub struct HybridPrivateKey {
+	/// the X25519 private key
+	x25519: SecretBytes,
+	/// the seed `d | z` of the ML-KEM-768 key pair
+	ml_kem_seed: Secret<[u8; 64]>,
+	x25519_sk: StaticSecret,
+	x25519_pk: PublicKey,
+	ml_kem_dk: DecapsulationKey,
+}
+
+impl HybridPrivateKey {
+	/// Build the private key of an X25519 private key and an ML-KEM-768 seed `d | z`.
+	pub fn new(x25519: SecretBytes, ml_kem_seed: Secret<[u8; 64]>) -> Self {
+		let x25519_sk = StaticSecret::from(*x25519.expose());
+		let x25519_pk = PublicKey::from(&x25519_sk);
+		let (ml_kem_dk, _) = ml_kem_keypair(&ml_kem_seed);
+		Self {
+			x25519,
+			ml_kem_seed,
+			x25519_sk,
+			x25519_pk,
+			ml_kem_dk,
+		}
+	}
+
+	/// The X25519 private key, e.g., to serialize the private key.
+	pub fn x25519(&self) -> &SecretBytes {
+		&self.x25519
+	}
+
+	/// The seed `d | z` of the ML-KEM-768 key pair, e.g., to serialize the private key.
+	pub fn ml_kem_seed(&self) -> &Secret<[u8; 64]> {
+		&self.ml_kem_seed
+	}
+}
+
+impl fmt::Debug for HybridPrivateKey {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str("HybridPrivateKey([REDACTED])")
+	}
+}
+
+/// Note encryption over a hybrid X25519 and ML-KEM-768 key exchange.
+pub struct HybridNoteEncryption;
+
+impl Ecies for HybridNoteEncryption {
+	type PublicKey = HybridPublicKey;
+	type PrivateKey = HybridPrivateKey;
+	type Message = NotePlaintext;
+	type Ciphertext = Vec<u8>;
+	type Error = EciesError;
+
+	/// Generate a pair of keys
+	fn keygen<R: RngCore + CryptoRng>(rng: &mut R) -> (Self::PublicKey, Self::PrivateKey) {
+		let x25519_sk = StaticSecret::new(&mut *rng);
+		let mut ml_kem_seed = Secret::new([0u8; 64]);
+		rng.fill_bytes(ml_kem_seed.expose_mut());
+		let (_, ek) = ml_kem_keypair(&ml_kem_seed);
+
+		let mut ml_kem = [0u8; HYBRID_KEM_PUBLIC_KEY_BYTES];
+		ml_kem.copy_from_slice(&ek.as_bytes());
+		(
+			HybridPublicKey {
+				x25519: PublicKey::from(&x25519_sk).to_bytes(),
+				ml_kem,
+			},
+			HybridPrivateKey::new(SecretBytes::new(x25519_sk.to_bytes()), ml_kem_seed),
+		)
+	}
+
+	/// Encrypt the note under the input public key.
+	///
+	/// # <weight>
+	/// Steps:
+	///     1. sample a random, ephemeral field element: ephemeral_sk
+	///     2. compute the shared secret ss = pk.x25519^ephemeral_sk
+	///     3. compute (kem_ciphertext, kem_ss) = ml_kem_encaps(pk.ml_kem)
+	///     4. set kdf = KDF(ss, kem_ss, ephemeral_pk, pk.x25519)
+	///     5. seal the note under kdf, as in `<NoteEncryption as Ecies>::encrypt`,
+	///        with aad = version | ephemeral_pk | kem_ciphertext | view_tag
+	///     6. return [version | ephemeral_pk | kem_ciphertext | view_tag | c | tag]
+	/// # </weight>
+	///
+	/// # Panics
+	/// Panics if the memo is longer than `NOTE_MEMO_MAX_BYTES`.
+	fn encrypt<R: RngCore + CryptoRng>(
+		pk: &Self::PublicKey,
+		message: &Self::Message,
+		rng: &mut R,
+	) -> Self::Ciphertext {
+		let ephemeral_sk = StaticSecret::new(&mut *rng);
+		let ephemeral_pk = PublicKey::from(&ephemeral_sk);
+		let shared_secret = ephemeral_sk.diffie_hellman(&PublicKey::from(pk.x25519));
+
+		let ek = EncapsulationKey::from_bytes(
+			&Encoded::<EncapsulationKey>::try_from(&pk.ml_kem[..])
+				.expect("the encapsulation key has a fixed length"),
+		);
+		let mut m = Secret::new([0u8; 32]);
+		rng.fill_bytes(m.expose_mut());
+		let (kem_ciphertext, kem_shared_secret) = ek
+			.encapsulate_deterministic(&B32::from(*m.expose()))
+			.expect("ML-KEM encapsulation never fails");
+
+		let mut kem_ss = SecretBytes::new([0u8; 32]);
+		kem_ss.expose_mut().copy_from_slice(&kem_shared_secret);
+		let kdf = MantaKdf::new_hybrid(
+			HYBRID_KDF_CONTEXT,
+			shared_secret.as_bytes(),
+			kem_ss.expose(),
+			ephemeral_pk.as_bytes(),
+			&pk.x25519,
+		);
+
+		let mut header = Vec::with_capacity(
+			HYBRID_HEADER_BYTES + NOTE_PLAINTEXT_HEADER_BYTES + message.memo.len() + TAG_BYTES,
+		);
+		header.push(HYBRID_NOTE_CIPHERTEXT_VERSION);
+		header.extend_from_slice(ephemeral_pk.as_bytes());
+		header.extend_from_slice(&kem_ciphertext);
+		seal_with_kdf(&kdf, header, message)
+	}
+
+	/// Decrypt a hybrid note ciphertext with a secret key.
+	///
+	/// # <weight>
+	/// Steps:
+	///     1. parse cipher as [version | ephemeral_pk | kem_ciphertext | view_tag | c | tag],
+	///        and check the version
+	///     2. compute the shared secret ss = ephemeral_pk^sk.x25519
+	///     3. compute kem_ss = ml_kem_decaps(sk.ml_kem, kem_ciphertext)
+	///     4. set kdf = KDF(ss, kem_ss, ephemeral_pk, pk.x25519), with pk.x25519 = g^sk.x25519,
+	///        which is computed once, when sk is built
+	///     5. open the note under kdf, as in `<NoteEncryption as Ecies>::decrypt`
+	/// # </weight>
+	fn decrypt(
+		sk: &Self::PrivateKey,
+		cipher: &Self::Ciphertext,
+	) -> Result<Self::Message, Self::Error> {
+		match cipher.first() {
+			None => return Err(EciesError::MalformedCiphertext),
+			Some(&version) if version != HYBRID_NOTE_CIPHERTEXT_VERSION => {
+				return Err(EciesError::UnsupportedVersion(version))
+			}
+			_ => {}
+		}
+		if cipher.len() < HYBRID_HEADER_BYTES + NOTE_PLAINTEXT_HEADER_BYTES + TAG_BYTES {
+			return Err(EciesError::MalformedCiphertext);
+		}
+		let (header, rest) = cipher.split_at(HYBRID_HEADER_BYTES);
+		let (body, tag) = rest.split_at(rest.len() - TAG_BYTES);
+
+		let mut ephemeral_pk = [0u8; 32];
+		ephemeral_pk.copy_from_slice(&header[1..33]);
+		let shared_secret = sk.x25519_sk.diffie_hellman(&PublicKey::from(ephemeral_pk));
+
+		let kem_ciphertext =
+			Ciphertext::<MlKem768>::try_from(&header[33..33 + HYBRID_KEM_CIPHERTEXT_BYTES])
+				.map_err(|_| EciesError::MalformedCiphertext)?;
+		let kem_shared_secret = sk
+			.ml_kem_dk
+			.decapsulate(&kem_ciphertext)
+			.map_err(|_| EciesError::AuthenticationFailure)?;
+
+		let mut kem_ss = SecretBytes::new([0u8; 32]);
+		kem_ss.expose_mut().copy_from_slice(&kem_shared_secret);
+		let kdf = MantaKdf::new_hybrid(
+			HYBRID_KDF_CONTEXT,
+			shared_secret.as_bytes(),
+			kem_ss.expose(),
+			&ephemeral_pk,
+			sk.x25519_pk.as_bytes(),
+		);
+		open_with_kdf(&kdf, header, body, tag)
+	}
+}
+
+/// The ML-KEM-768 key pair of a seed `d | z`.
+fn ml_kem_keypair(seed: &Secret<[u8; 64]>) -> (DecapsulationKey, EncapsulationKey) {
+	let mut d = SecretBytes::new([0u8; 32]);
+	let mut z = SecretBytes::new([0u8; 32]);
+	d.expose_mut().copy_from_slice(&seed.expose()[0..32]);
+	z.expose_mut().copy_from_slice(&seed.expose()[32..64]);
+	MlKem768::generate_deterministic(&B32::from(*d.expose()), &B32::from(*z.expose()))
+}
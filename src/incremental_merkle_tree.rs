@@ -0,0 +1,217 @@
+// Copyright 2019-2021 Manta Network.
+// This file is part of manta-crypto.
+//
+// manta-crypto is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// manta-crypto is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with manta-crypto.  If not, see <http://www.gnu.org/licenses/>.
+
+//! This file implements an append-only merkle tree that keeps only its
+//! frontier: for every level `l`, the root of the latest complete subtree
+//! of `2^l` leaves, if there is one. The frontier behaves like a binary
+//! counter of the leaves, so that a leaf is appended, and the root is
+//! recomputed, in O(HEIGHT) hashes.
+//!
+//! The roots of a `Legacy` tree are identical to those of
+//! `ark_crypto_primitives::MerkleTree`, and a `Tagged` tree has the same shape.
+//! The ark tree pads the leaves to the next power of two `2^k` with empty
+//! leaves, and then hashes its root with an empty leaf, rather than with an
+//! empty subtree, until it reaches the height:
+//! ```text
+//! root = H(...H(H(bottom_root, e), e)..., e),  with max(HEIGHT - 1 - k, 1) hashes
+//! ```
+use crate::merkle_tree::{
+	hash_empty, hash_leaf, hash_node, MerkleTreeError, TreeDigest, TreeHashing,
+};
+use ark_crypto_primitives::FixedLengthCRH;
+use ark_ff::ToBytes;
+use ark_std::{vec, vec::Vec};
+
+/// An append-only merkle tree that stores only its rightmost path.
+pub struct IncrementalMerkleTree<P: TreeHashing> {
+	pub(crate) param: <P::H as FixedLengthCRH>::Parameters,
+	pub(crate) frontier: Frontier<P>,
+	/// `empty[l]` is the root of a subtree of `2^l` empty leaves
+	pub(crate) empty: Vec<TreeDigest<P>>,
+}
+
+/// The frontier of a tree, or of a subtree.
+pub(crate) struct Frontier<P: TreeHashing> {
+	/// the number of leaves
+	pub(crate) size: usize,
+	/// `filled[l]` is the root of the latest complete subtree of `2^l` leaves,
+	/// if the bit `l` of `size` is set
+	pub(crate) filled: Vec<Option<TreeDigest<P>>>,
+}
+
+impl<P: TreeHashing> Clone for IncrementalMerkleTree<P> {
+	fn clone(&self) -> Self {
+		Self {
+			param: self.param.clone(),
+			frontier: self.frontier.clone(),
+			empty: self.empty.clone(),
+		}
+	}
+}
+
+impl<P: TreeHashing> Clone for Frontier<P> {
+	fn clone(&self) -> Self {
+		Self {
+			size: self.size,
+			filled: self.filled.clone(),
+		}
+	}
+}
+
+impl<P: TreeHashing> IncrementalMerkleTree<P> {
+	/// The maximum number of leaves, as for `ark_crypto_primitives::MerkleTree`.
+	pub const CAPACITY: usize = 1 << (P::HEIGHT - 1);
+
+	/// Build an empty tree.
+	pub fn new(param: <P::H as FixedLengthCRH>::Parameters) -> Result<Self, MerkleTreeError> {
+		let mut empty = vec![hash_empty::<P::H>(&param)?];
+		for level in 1..P::HEIGHT {
+			let node = hash_node::<P>(&param, &empty[level - 1], &empty[level - 1])?;
+			empty.push(node);
+		}
+		Ok(Self {
+			param,
+			frontier: Frontier::new(),
+			empty,
+		})
+	}
+
+	/// Build a tree from the leaves, in order.
+	pub fn from_leaves<L: ToBytes>(
+		param: <P::H as FixedLengthCRH>::Parameters,
+		leaves: &[L],
+	) -> Result<Self, MerkleTreeError> {
+		let mut tree = Self::new(param)?;
+		for leaf in leaves {
+			tree.append(leaf)?;
+		}
+		Ok(tree)
+	}
+
+	/// The number of leaves of the tree.
+	pub fn len(&self) -> usize {
+		self.frontier.size
+	}
+
+	/// Whether the tree has no leaves.
+	pub fn is_empty(&self) -> bool {
+		self.frontier.size == 0
+	}
+
+	/// Append a leaf to the tree, and return its index.
+	///
+	/// # <weight>
+	/// Steps:
+	///     1. set node = H(leaf), and level = 0
+	///     2. while filled[level] is set:
+	///         a. set node = H(filled[level], node), and clear filled[level]
+	///         b. increment level
+	///     3. set filled[level] = node
+	/// # </weight>
+	pub fn append<L: ToBytes>(&mut self, leaf: &L) -> Result<usize, MerkleTreeError> {
+		if self.frontier.size == Self::CAPACITY {
+			return Err(MerkleTreeError::TreeFull);
+		}
+		let node = hash_leaf::<P, _>(&self.param, leaf)?;
+		self.frontier.append(&self.param, node)?;
+		Ok(self.frontier.size - 1)
+	}
+
+	/// The root of the tree.
+	///
+	/// # <weight>
+	/// Steps:
+	///     1. set k = log2 of the number of leaves, rounded up
+	///     2. compute the root of the bottom tree of 2^k leaves from the frontier,
+	///        where the missing right children are empty subtrees
+	///     3. hash the bottom root with the empty leaf max(HEIGHT - 1 - k, 1) times
+	/// # </weight>
+	pub fn root(&self) -> Result<TreeDigest<P>, MerkleTreeError> {
+		let depth = self.bottom_depth();
+		let mut root = self
+			.frontier
+			.subtree_root(&self.param, &self.empty, depth)?;
+		for _ in 0..Self::padding(depth) {
+			root = hash_node::<P>(&self.param, &root, &self.empty[0])?;
+		}
+		Ok(root)
+	}
+
+	/// The depth `k` of the bottom tree, that holds the leaves.
+	pub(crate) fn bottom_depth(&self) -> usize {
+		self.frontier
+			.size
+			.max(1)
+			.next_power_of_two()
+			.trailing_zeros() as usize
+	}
+
+	/// The number of hashes with the empty leaf above a bottom tree of `depth`.
+	pub(crate) fn padding(depth: usize) -> usize {
+		(P::HEIGHT - 1).saturating_sub(depth).max(1)
+	}
+}
+
+impl<P: TreeHashing> Frontier<P> {
+	/// The frontier of an empty tree.
+	pub(crate) fn new() -> Self {
+		Self {
+			size: 0,
+			filled: vec![None; P::HEIGHT],
+		}
+	}
+
+	/// Append the hash of a leaf; the caller checks the capacity.
+	pub(crate) fn append(
+		&mut self,
+		param: &<P::H as FixedLengthCRH>::Parameters,
+		mut node: TreeDigest<P>,
+	) -> Result<(), MerkleTreeError> {
+		let mut level = 0;
+		while let Some(left) = self.filled[level].take() {
+			node = hash_node::<P>(param, &left, &node)?;
+			level += 1;
+		}
+		self.filled[level] = Some(node);
+		self.size += 1;
+		Ok(())
+	}
+
+	/// The root of the subtree of the first `2^depth` leaves, where the
+	/// missing leaves are empty. The frontier holds at most `2^depth` leaves.
+	pub(crate) fn subtree_root(
+		&self,
+		param: &<P::H as FixedLengthCRH>::Parameters,
+		empty: &[TreeDigest<P>],
+		depth: usize,
+	) -> Result<TreeDigest<P>, MerkleTreeError> {
+		if self.size == 1 << depth {
+			return Ok(self.filled[depth]
+				.clone()
+				.expect("a full subtree is a single filled subtree"));
+		}
+		let mut node: Option<TreeDigest<P>> = None;
+		for level in 0..depth {
+			node = match (&self.filled[level], node) {
+				(Some(left), Some(right)) => Some(hash_node::<P>(param, left, &right)?),
+				(Some(left), None) => Some(hash_node::<P>(param, left, &empty[level])?),
+				(None, Some(left)) => Some(hash_node::<P>(param, &left, &empty[level])?),
+				(None, None) => None,
+			};
+		}
+		Ok(node.unwrap_or_else(|| empty[depth].clone()))
+	}
+}
@@ -0,0 +1,203 @@
+// Copyright 2019-2021 Manta Network.
+// This file is part of manta-crypto.
+//
+// manta-crypto is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// manta-crypto is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with manta-crypto.  If not, see <http://www.gnu.org/licenses/>.
+
+//! This file implements hierarchical deterministic derivation of shielded
+//! accounts from a seed. It follows BIP-32, but only hardened children
+//! exist, and HMAC-Blake2b-512 replaces HMAC-SHA512:
+//! ```text
+//! master   = HMAC(MASTER_KEY, seed)
+//! child(i) = HMAC(chain_code, 0x00 | key | ser32(i + 2^31))
+//! ```
+//! where the left and right halves of each output are the key and the
+//! chain code. Every account key is derived from the spending key:
+//! ```text
+//! spending key -> viewing key -> Ecies key pair
+//!                             -> outgoing viewing key
+//! ```
+//! so that a viewing key can be shared without the ability to spend.
+//!
+//! # Test vectors
+//! The seed is the 32 bytes `00 01 02 ... 1f`, and every value is in hex.
+//! ```text
+//! m                     key         897549e6199199d64ef90d841a863af6976e488e13fcd1a17f814fb9404a674e
+//!                       chain code  6abeacd084abfac27a5444a3bf748a9f37114a7973d67813c53ce2c625bf32e6
+//! m/44'/611'/0'         key         57a4cc0ecbc7995ba2df074597574df9a8f0d0bda2b78a14ccf90f1061e74197
+//!                       chain code  bf21c54c0b9b95ad33662b4d0254bcbc40d15685866a4764468508f53174ab83
+//!   viewing key                     a967ab6e5610b0a59c7fccc87c446820ad8844ec9db46ecd6524f709b7663da6
+//!   ecies public key                2fdc1be4243db871bf75bbe2d0f165a1303b46cd640b3ce5256a730b36278c7f
+//!   ecies private key               f0f5a41867fbaf031fac7ca0c97e8c1e80432048468bdb517736d9629a719f63
+//!   outgoing viewing key            670399773bff34ebf9eaa83d034368cf94044550785cb0ad79a8a8b17f5d04f7
+//! ```
+use crate::SecretBytes;
+use ark_std::vec::Vec;
+use blake2::Blake2b;
+use hmac::{Hmac, Mac, NewMac};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+/// The HMAC key of the master key derivation.
+const MASTER_KEY: &[u8] = b"Manta shielded seed";
+/// The label of the viewing key.
+const VIEWING_KEY_LABEL: &[u8] = b"manta viewing key";
+/// The label of the `Ecies` private key.
+const ECIES_KEY_LABEL: &[u8] = b"manta ecies key";
+/// The label of the outgoing viewing key.
+const OUTGOING_VIEWING_KEY_LABEL: &[u8] = b"manta outgoing viewing key";
+/// The offset of hardened indices.
+pub const HARDENED_OFFSET: u32 = 1 << 31;
+
+/// Errors of the key derivation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DerivationError {
+	/// The seed is shorter than 16 bytes or longer than 64 bytes.
+	InvalidSeedLength,
+	/// The child index does not fit below `HARDENED_OFFSET`.
+	IndexOutOfRange(u32),
+	/// The path is not of the form `m/i'/j'/...`.
+	MalformedPath,
+	/// The depth of the key would exceed 255.
+	MaxDepth,
+}
+
+impl core::fmt::Display for DerivationError {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		core::fmt::Debug::fmt(self, f)
+	}
+}
+
+impl ark_std::error::Error for DerivationError {}
+
+/// A spending key together with the chain code of its children.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtendedSpendingKey {
+	/// the depth of the key; the master key has depth 0
+	pub depth: u8,
+	/// the hardened index of the key; 0 for the master key
+	pub child_index: u32,
+	/// the chain code
+	pub chain_code: SecretBytes,
+	/// the spending key
+	pub key: SecretBytes,
+}
+
+impl ExtendedSpendingKey {
+	/// Derive the master key of `seed`.
+	pub fn master(seed: &[u8]) -> Result<Self, DerivationError> {
+		if seed.len() < 16 || seed.len() > 64 {
+			return Err(DerivationError::InvalidSeedLength);
+		}
+		let (key, chain_code) = hmac_blake2b(MASTER_KEY, &[seed]);
+		Ok(Self {
+			depth: 0,
+			child_index: 0,
+			chain_code,
+			key,
+		})
+	}
+
+	/// Derive the hardened child `index`, i.e., `index'`.
+	pub fn child(&self, index: u32) -> Result<Self, DerivationError> {
+		if index >= HARDENED_OFFSET {
+			return Err(DerivationError::IndexOutOfRange(index));
+		}
+		let depth = self.depth.checked_add(1).ok_or(DerivationError::MaxDepth)?;
+		let child_index = index + HARDENED_OFFSET;
+		let (key, chain_code) = hmac_blake2b(
+			self.chain_code.expose(),
+			&[&[0u8], self.key.expose(), &child_index.to_be_bytes()],
+		);
+		Ok(Self {
+			depth,
+			child_index,
+			chain_code,
+			key,
+		})
+	}
+
+	/// Derive the descendant at `path`, relative to this key.
+	pub fn derive_path(&self, path: &[u32]) -> Result<Self, DerivationError> {
+		path.iter()
+			.try_fold(self.clone(), |key, index| key.child(*index))
+	}
+
+	/// The viewing key of the account.
+	pub fn viewing_key(&self) -> ViewingKey {
+		ViewingKey(prf(&self.key, VIEWING_KEY_LABEL))
+	}
+}
+
+/// A key that can see the notes of an account, but cannot spend them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ViewingKey(pub SecretBytes);
+
+impl ViewingKey {
+	/// The `Ecies` key pair `(pk, sk)` that incoming notes are encrypted to.
+	pub fn ecies_keypair(&self) -> ([u8; 32], SecretBytes) {
+		let sk = StaticSecret::from(*prf(&self.0, ECIES_KEY_LABEL).expose());
+		(
+			PublicKey::from(&sk).to_bytes(),
+			SecretBytes::new(sk.to_bytes()),
+		)
+	}
+
+	/// The outgoing viewing key of the account.
+	pub fn outgoing_viewing_key(&self) -> SecretBytes {
+		prf(&self.0, OUTGOING_VIEWING_KEY_LABEL)
+	}
+}
+
+/// Parse a path of the form `m/i'/j'/...` into its indices.
+/// Every index must be hardened, marked by `'` or `h`.
+pub fn parse_derivation_path(path: &str) -> Result<Vec<u32>, DerivationError> {
+	let mut parts = path.split('/');
+	if parts.next() != Some("m") {
+		return Err(DerivationError::MalformedPath);
+	}
+	parts
+		.map(|part| {
+			let index = part
+				.strip_suffix('\'')
+				.or_else(|| part.strip_suffix('h'))
+				.ok_or(DerivationError::MalformedPath)?;
+			let index = index
+				.parse::<u32>()
+				.map_err(|_| DerivationError::MalformedPath)?;
+			if index >= HARDENED_OFFSET {
+				return Err(DerivationError::IndexOutOfRange(index));
+			}
+			Ok(index)
+		})
+		.collect()
+}
+
+/// HMAC-Blake2b-512 over the concatenation of `data`, split into halves.
+fn hmac_blake2b(key: &[u8], data: &[&[u8]]) -> (SecretBytes, SecretBytes) {
+	let mut mac = Hmac::<Blake2b>::new_from_slice(key).expect("HMAC accepts keys of any length");
+	for d in data {
+		mac.update(d);
+	}
+	let output = mac.finalize().into_bytes();
+
+	let mut left = SecretBytes::new([0u8; 32]);
+	let mut right = SecretBytes::new([0u8; 32]);
+	left.expose_mut().copy_from_slice(&output[0..32]);
+	right.expose_mut().copy_from_slice(&output[32..64]);
+	(left, right)
+}
+
+/// A pseudo-random function keyed with `key`, that derives sub-keys by label.
+fn prf(key: &SecretBytes, label: &[u8]) -> SecretBytes {
+	hmac_blake2b(key.expose(), &[label]).0
+}
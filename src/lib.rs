@@ -15,25 +15,96 @@
 // along with manta-crypto.  If not, see <http://www.gnu.org/licenses/>.
 #![no_std]
 
+#[cfg(feature = "std")]
+extern crate std;
+
+mod address;
+mod asset;
+mod checkpointed_merkle_tree;
 mod checksum;
 mod commitment;
+mod commitment_context;
 mod constants;
 mod ecies;
+#[cfg(feature = "pq-hybrid")]
+mod hybrid_encryption;
+mod incremental_merkle_tree;
+mod key_derivation;
+mod membership;
 mod merkle_tree;
+mod multi_recipient;
+mod node_storage;
+mod note_encryption;
 mod param;
+mod poseidon;
+mod scanner;
+mod secret;
 mod serdes;
+mod sparse_merkle_tree;
+mod stealth;
+mod var_length_hash;
+mod verifiable_encryption;
+mod witness_tracker;
 mod zkp;
 
 #[cfg(test)]
 mod tests;
 
+pub use address::{AddressError, Network, ShieldedAddress, ADDRESS_VERSION};
+pub use asset::{
+	asset_commitment_gadget, asset_commitment_input, enforce_same_asset, AssetCommitment, AssetId,
+};
+pub use checkpointed_merkle_tree::CheckpointedMerkleTree;
 pub use checksum::Checksum;
-pub use commitment::Commitment;
+pub use commitment::{Commitment, CommitmentError};
+pub use commitment_context::CommitmentContext;
 pub use constants::{COMMIT_PARAM, HASH_PARAM};
-pub use ecies::Ecies;
-pub use merkle_tree::MerkleTree;
+pub use ecies::{
+	decrypt_legacy, Ecies, EciesError, MantaKdf, MANTA_KDF_ENCRYPTION_LABEL, MANTA_KDF_TAG_LABEL,
+};
+#[cfg(feature = "pq-hybrid")]
+pub use hybrid_encryption::{
+	HybridNoteEncryption, HybridPrivateKey, HybridPublicKey, HYBRID_KEM_PUBLIC_KEY_BYTES,
+	HYBRID_NOTE_CIPHERTEXT_VERSION,
+};
+pub use incremental_merkle_tree::IncrementalMerkleTree;
+pub use key_derivation::{
+	parse_derivation_path, DerivationError, ExtendedSpendingKey, ViewingKey, HARDENED_OFFSET,
+};
+pub use membership::{MembershipPath, MembershipPathVar};
+pub use merkle_tree::{
+	MerkleTree, MerkleTreeError, MerkleTreePath, TreeDigest, TreeHashing, TreeVersion,
+	VersionedRoot,
+};
+pub use multi_recipient::{
+	decrypt_multi_recipient_note, encrypt_note_to_many, MULTI_RECIPIENT_CIPHERTEXT_VERSION,
+	MULTI_RECIPIENT_MAX_RECIPIENTS,
+};
+#[cfg(feature = "std")]
+pub use node_storage::FileNodeStorage;
+pub use node_storage::{MemoryNodeStorage, NodeStorage, StoredMerkleTree};
+pub use note_encryption::{
+	encrypt_note_with_ovk, recover_outgoing_note, NoteEncryption, NotePlaintext,
+	NOTE_CIPHERTEXT_VERSION, NOTE_MEMO_MAX_BYTES, OUTGOING_CIPHERTEXT_BYTES,
+};
 pub use param::*;
+pub use poseidon::{PoseidonCRH, PoseidonCRHGadget, PoseidonParameters, PoseidonParametersVar};
+#[cfg(feature = "std")]
+pub use scanner::par_scan_notes;
+pub use scanner::scan_notes;
+pub use secret::{Secret, SecretBytes};
 pub use serdes::MantaSerDes;
+pub use sparse_merkle_tree::{
+	SparseMerkleProof, SparseMerkleProofVar, SparseMerkleTree, SPARSE_MERKLE_TREE_DEPTH,
+};
+pub use stealth::{OneTimeAddress, StealthAddress, StealthSpendingKey, StealthViewingKey};
+pub use var_length_hash::{
+	var_length_hash, var_length_hash_gadget, VariableLengthHash, VAR_LENGTH_HASH_BLOCK_BYTES,
+};
+pub use verifiable_encryption::{
+	verifiable_encryption_gadget, JubjubCiphertext, JubjubCiphertextVar, VerifiableEncryption,
+};
+pub use witness_tracker::WitnessTracker;
 pub use zkp::{MantaZKPVerifier, RECLAIM_PK, TRANSFER_PK};
 
 pub struct MantaCrypto;
@@ -0,0 +1,341 @@
+// Copyright 2019-2021 Manta Network.
+// This file is part of manta-crypto.
+//
+// manta-crypto is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// manta-crypto is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with manta-crypto.  If not, see <http://www.gnu.org/licenses/>.
+
+//! This file implements membership paths on the ledger merkle tree,
+//! together with their gadget. A path is the index of the leaf, and the
+//! siblings of the nodes from the leaf up to the root:
+//! ```text
+//! node_0     = H(leaf_tag | leaf)
+//! node_{l+1} = H(node_tag | node_l | sibling_l)  if the bit l of index is 0
+//!              H(node_tag | sibling_l | node_l)  otherwise
+//! root       = node_{len(siblings)}
+//! ```
+//! where the tags are empty on a `Legacy` tree, and a `Tagged` tree hashes
+//! the x coordinates of the children, see `TreeVersion`.
+//! The paths follow the shape of `ark_crypto_primitives::MerkleTree`, so that
+//! the siblings above the bottom tree of the leaves are the empty leaf.
+//! A path thus has `HEIGHT - 1` siblings, or `HEIGHT` once the bottom tree
+//! is the whole tree, i.e., once it holds more than `2^(HEIGHT - 2)` leaves.
+//! The gadget always allocates `HEIGHT` levels and selects the root of the
+//! right length, so that a circuit has a single shape for every path.
+//! Unlike `AccountMembershipVar`, the gadget fixes the position of the leaf.
+use crate::{
+	merkle_tree::{
+		hash_empty, hash_leaf, hash_node, MerkleTreeError, TreeDigest, TreeHashing,
+		TAGGED_CHILD_BYTES,
+	},
+	serdes::MantaSerDes,
+};
+use ark_crypto_primitives::{FixedLengthCRH, FixedLengthCRHGadget};
+use ark_ed_on_bls12_381::Fq;
+use ark_ff::ToBytes;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{Namespace, SynthesisError};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use ark_std::{
+	borrow::Borrow,
+	io::{Read, Write},
+	marker::PhantomData,
+	vec::Vec,
+};
+use manta_error::MantaError;
+
+/// The path from a leaf to the root of a merkle tree.
+pub struct MembershipPath<P: TreeHashing> {
+	/// the index of the leaf
+	pub index: usize,
+	/// the siblings of the nodes on the path, from the leaf up
+	pub siblings: Vec<TreeDigest<P>>,
+}
+
+impl<P: TreeHashing> Clone for MembershipPath<P> {
+	fn clone(&self) -> Self {
+		Self {
+			index: self.index,
+			siblings: self.siblings.clone(),
+		}
+	}
+}
+
+impl<P: TreeHashing> PartialEq for MembershipPath<P> {
+	fn eq(&self, other: &Self) -> bool {
+		self.index == other.index && self.siblings == other.siblings
+	}
+}
+
+impl<P: TreeHashing> core::fmt::Debug for MembershipPath<P> {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		f.debug_struct("MembershipPath")
+			.field("index", &self.index)
+			.field("siblings", &self.siblings)
+			.finish()
+	}
+}
+
+/// The path of the first leaf of an empty tree, e.g., for circuit setup;
+/// the gadget has the same shape for every path.
+impl<P: TreeHashing> Default for MembershipPath<P> {
+	fn default() -> Self {
+		Self {
+			index: 0,
+			siblings: ark_std::vec![TreeDigest::<P>::default(); P::HEIGHT - 1],
+		}
+	}
+}
+
+impl<P: TreeHashing> MembershipPath<P> {
+	/// Generate the path of the leaf at `index` of the tree of `leaves`.
+	///
+	/// # <weight>
+	/// Steps:
+	///     1. hash the leaves, and pad them with the empty leaf to the next power of two
+	///     2. while more than one node is left:
+	///         a. push the sibling of the node on the path
+	///         b. hash the nodes in pairs
+	///     3. push the empty leaf as a sibling up to the height of the tree
+	/// # </weight>
+	pub fn from_leaves<L: ToBytes>(
+		param: &<P::H as FixedLengthCRH>::Parameters,
+		leaves: &[L],
+		index: usize,
+	) -> Result<Self, MerkleTreeError> {
+		if leaves.len() > 1 << (P::HEIGHT - 1) {
+			return Err(MerkleTreeError::TreeFull);
+		}
+		if index >= leaves.len() {
+			return Err(MerkleTreeError::InvalidLeafIndex);
+		}
+		let empty = hash_empty::<P::H>(param)?;
+		let mut nodes = leaves
+			.iter()
+			.map(|leaf| hash_leaf::<P, _>(param, leaf))
+			.collect::<Result<Vec<_>, _>>()?;
+		nodes.resize(leaves.len().next_power_of_two(), empty.clone());
+
+		let mut siblings = Vec::with_capacity(P::HEIGHT);
+		let mut position = index;
+		while nodes.len() > 1 {
+			siblings.push(nodes[position ^ 1].clone());
+			nodes = nodes
+				.chunks(2)
+				.map(|pair| hash_node::<P>(param, &pair[0], &pair[1]))
+				.collect::<Result<Vec<_>, _>>()?;
+			position >>= 1;
+		}
+		let padding = (P::HEIGHT - 1).saturating_sub(siblings.len()).max(1);
+		siblings.extend(ark_std::iter::repeat(empty).take(padding));
+		Ok(Self { index, siblings })
+	}
+
+	/// The root of the tree, if `leaf` is at the end of the path.
+	pub fn root<L: ToBytes>(
+		&self,
+		param: &<P::H as FixedLengthCRH>::Parameters,
+		leaf: &L,
+	) -> Result<TreeDigest<P>, MerkleTreeError> {
+		let mut node = hash_leaf::<P, _>(param, leaf)?;
+		for (level, sibling) in self.siblings.iter().enumerate() {
+			node = if index_bit(self.index, level) {
+				hash_node::<P>(param, sibling, &node)?
+			} else {
+				hash_node::<P>(param, &node, sibling)?
+			};
+		}
+		Ok(node)
+	}
+
+	/// Verify that `leaf` is at the index of the path on the tree of `root`.
+	pub fn verify<L: ToBytes>(
+		&self,
+		param: &<P::H as FixedLengthCRH>::Parameters,
+		root: &TreeDigest<P>,
+		leaf: &L,
+	) -> Result<bool, MerkleTreeError> {
+		// the index must fit into the bits of the path
+		if (self.siblings.len()..64).any(|level| index_bit(self.index, level)) {
+			return Ok(false);
+		}
+		Ok(self.root(param, leaf)? == *root)
+	}
+}
+
+impl<P: TreeHashing> MantaSerDes for MembershipPath<P> {
+	/// Serialize the path as `index (u64) | len(siblings) (u64) | siblings`.
+	fn serialize<W: Write>(&self, mut writer: W) -> Result<(), MantaError> {
+		(self.index as u64).serialize(&mut writer)?;
+		self.siblings.serialize(&mut writer)?;
+		Ok(())
+	}
+
+	/// Deserialize a path. The siblings are checked to be valid digests.
+	fn deserialize<R: Read>(mut reader: R) -> Result<Self, MantaError> {
+		let index = u64::deserialize(&mut reader)? as usize;
+		let siblings = Vec::<TreeDigest<P>>::deserialize(&mut reader)?;
+		Ok(Self { index, siblings })
+	}
+}
+
+/// The gadget of a `MembershipPath`.
+pub struct MembershipPathVar<P: TreeHashing, HG: FixedLengthCRHGadget<P::H, Fq>> {
+	/// whether the path has `HEIGHT` siblings rather than `HEIGHT - 1`
+	full: Boolean<Fq>,
+	/// the `HEIGHT` bits of the index, from the leaf up
+	index: Vec<Boolean<Fq>>,
+	/// the `HEIGHT` siblings of the nodes on the path, from the leaf up;
+	/// the last one is a dummy if the path is not full
+	siblings: Vec<HG::OutputVar>,
+	_config: PhantomData<P>,
+}
+
+impl<P: TreeHashing, HG: FixedLengthCRHGadget<P::H, Fq>> AllocVar<MembershipPath<P>, Fq>
+	for MembershipPathVar<P, HG>
+{
+	fn new_variable<T: Borrow<MembershipPath<P>>>(
+		cs: impl Into<Namespace<Fq>>,
+		f: impl FnOnce() -> Result<T, SynthesisError>,
+		mode: AllocationMode,
+	) -> Result<Self, SynthesisError> {
+		let ns = cs.into();
+		let cs = ns.cs();
+		// the path is missing at setup, where only the shape matters
+		let path = f();
+		let path: Result<&MembershipPath<P>, SynthesisError> = match &path {
+			Ok(path) => Ok(path.borrow()),
+			Err(e) => Err(*e),
+		};
+		if let Ok(path) = path {
+			if path.siblings.len() + 1 != P::HEIGHT && path.siblings.len() != P::HEIGHT {
+				return Err(SynthesisError::Unsatisfiable);
+			}
+		}
+		let full = Boolean::new_variable(
+			cs.clone(),
+			|| path.map(|path| path.siblings.len() == P::HEIGHT),
+			mode,
+		)?;
+		let index = (0..P::HEIGHT)
+			.map(|level| {
+				Boolean::new_variable(
+					cs.clone(),
+					|| path.map(|path| index_bit(path.index, level)),
+					mode,
+				)
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+		let siblings = (0..P::HEIGHT)
+			.map(|level| {
+				HG::OutputVar::new_variable(
+					cs.clone(),
+					|| path.map(|path| path.siblings.get(level).cloned().unwrap_or_default()),
+					mode,
+				)
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+		Ok(Self {
+			full,
+			index,
+			siblings,
+			_config: PhantomData,
+		})
+	}
+}
+
+impl<P: TreeHashing, HG: FixedLengthCRHGadget<P::H, Fq>> MembershipPathVar<P, HG> {
+	/// Gadget for `MembershipPath::root`, from the bytes of the leaf.
+	pub fn root(
+		&self,
+		param: &HG::ParametersVar,
+		leaf: &[UInt8<Fq>],
+	) -> Result<HG::OutputVar, SynthesisError> {
+		let mut input = tag_gadget(P::VERSION.leaf_tag());
+		input.extend_from_slice(leaf);
+		let mut nodes = Vec::with_capacity(P::HEIGHT + 1);
+		nodes.push(hash_padded_gadget::<P::H, HG>(param, input)?);
+		for (bit, sibling) in self.index.iter().zip(self.siblings.iter()) {
+			let node = &nodes[nodes.len() - 1];
+			let left = HG::OutputVar::conditionally_select(bit, sibling, node)?;
+			let right = HG::OutputVar::conditionally_select(bit, node, sibling)?;
+			nodes.push(hash_children_gadget::<P::H, HG>(
+				param,
+				P::VERSION.node_tag(),
+				&left,
+				&right,
+			)?);
+		}
+		// the root is after `HEIGHT` hashes on a full path, and `HEIGHT - 1` otherwise
+		HG::OutputVar::conditionally_select(&self.full, &nodes[P::HEIGHT], &nodes[P::HEIGHT - 1])
+	}
+
+	/// Gadget for `MembershipPath::verify`.
+	pub fn check_membership(
+		&self,
+		param: &HG::ParametersVar,
+		root: &HG::OutputVar,
+		leaf: &[UInt8<Fq>],
+	) -> Result<Boolean<Fq>, SynthesisError> {
+		self.root(param, leaf)?.is_eq(root)
+	}
+}
+
+/// The bit `level` of the leaf index, i.e., whether the node is a right child.
+fn index_bit(index: usize, level: usize) -> bool {
+	(index as u64).checked_shr(level as u32).unwrap_or(0) & 1 == 1
+}
+
+/// The tag of a hash input, as constant bytes.
+fn tag_gadget(tag: Option<u8>) -> Vec<UInt8<Fq>> {
+	tag.into_iter().map(UInt8::constant).collect()
+}
+
+/// Gadget for `hash_inner_node`.
+pub(crate) fn hash_inner_node_gadget<H: FixedLengthCRH, HG: FixedLengthCRHGadget<H, Fq>>(
+	param: &HG::ParametersVar,
+	left: &HG::OutputVar,
+	right: &HG::OutputVar,
+) -> Result<HG::OutputVar, SynthesisError> {
+	hash_children_gadget::<H, HG>(param, None, left, right)
+}
+
+/// Gadget for the hash of an inner node, with the tag of its version.
+fn hash_children_gadget<H: FixedLengthCRH, HG: FixedLengthCRHGadget<H, Fq>>(
+	param: &HG::ParametersVar,
+	tag: Option<u8>,
+	left: &HG::OutputVar,
+	right: &HG::OutputVar,
+) -> Result<HG::OutputVar, SynthesisError> {
+	let mut bytes = tag_gadget(tag);
+	for child in [left, right].iter() {
+		let mut child = child.to_bytes()?;
+		if tag.is_some() {
+			child.truncate(TAGGED_CHILD_BYTES);
+		}
+		bytes.extend_from_slice(&child);
+	}
+	hash_padded_gadget::<H, HG>(param, bytes)
+}
+
+/// Gadget for `hash_padded`.
+pub(crate) fn hash_padded_gadget<H: FixedLengthCRH, HG: FixedLengthCRHGadget<H, Fq>>(
+	param: &HG::ParametersVar,
+	mut input: Vec<UInt8<Fq>>,
+) -> Result<HG::OutputVar, SynthesisError> {
+	let input_bytes = H::INPUT_SIZE_BITS / 8;
+	if input.len() > input_bytes {
+		return Err(SynthesisError::Unsatisfiable);
+	}
+	input.resize(input_bytes, UInt8::constant(0));
+	HG::evaluate(param, &input)
+}
@@ -15,9 +15,144 @@
 // along with manta-crypto.  If not, see <http://www.gnu.org/licenses/>.
 
 use crate::*;
-use ark_serialize::CanonicalSerialize;
+use ark_crypto_primitives::FixedLengthCRH;
+use ark_ff::ToBytes;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use ark_std::{
+	io::{Read, Write},
+	vec::Vec,
+};
 use manta_error::MantaError;
 
+/// Errors of the merkle trees.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MerkleTreeError {
+	/// The tree holds `2^(HEIGHT - 1)` leaves already.
+	TreeFull,
+	/// The input of a hash is longer than the hash function accepts.
+	HashFailure,
+	/// The index is not the index of a leaf of the tree.
+	InvalidLeafIndex,
+	/// There is no checkpoint to rewind to.
+	NoCheckpoint,
+	/// A proof does not have the number of siblings of the tree.
+	MalformedProof,
+	/// A node is missing from the storage, or cannot be read or written.
+	StorageFailure,
+	/// The version of a root is not a known `TreeVersion`.
+	UnknownVersion,
+	/// The tree does not support the version.
+	UnsupportedVersion(TreeVersion),
+}
+
+impl core::fmt::Display for MerkleTreeError {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		core::fmt::Debug::fmt(self, f)
+	}
+}
+
+impl ark_std::error::Error for MerkleTreeError {}
+
+/// The prefix of the hash input of a leaf, in a tagged tree.
+const LEAF_TAG: u8 = 1;
+/// The prefix of the hash input of an inner node, in a tagged tree.
+const NODE_TAG: u8 = 2;
+/// The number of bytes of a child in the hash input of a tagged inner node,
+/// i.e., the x coordinate of a Pedersen digest, as in `var_length_hash`.
+pub(crate) const TAGGED_CHILD_BYTES: usize = 32;
+
+/// The version of the hashing of a merkle tree, which its roots commit to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TreeVersion {
+	/// The hashing of `ark_crypto_primitives::MerkleTree`, where a leaf and
+	/// an inner node are hashed alike.
+	Legacy = 0,
+	/// The hash input of a leaf is prefixed with a leaf tag, and the one of
+	/// an inner node with a node tag, so that a leaf is never an inner node.
+	/// An inner node hashes the first `TAGGED_CHILD_BYTES` of its children,
+	/// i.e., their x coordinates, so that `tag | left | right` fits the input
+	/// of the Pedersen `Hash`. The empty leaf is the hash of zeros, with no tag.
+	Tagged = 1,
+}
+
+impl TreeVersion {
+	/// The prefix of the hash input of a leaf.
+	pub(crate) fn leaf_tag(self) -> Option<u8> {
+		match self {
+			TreeVersion::Legacy => None,
+			TreeVersion::Tagged => Some(LEAF_TAG),
+		}
+	}
+
+	/// The prefix of the hash input of an inner node.
+	pub(crate) fn node_tag(self) -> Option<u8> {
+		match self {
+			TreeVersion::Legacy => None,
+			TreeVersion::Tagged => Some(NODE_TAG),
+		}
+	}
+}
+
+impl core::convert::TryFrom<u8> for TreeVersion {
+	type Error = MerkleTreeError;
+
+	fn try_from(version: u8) -> Result<Self, Self::Error> {
+		match version {
+			0 => Ok(TreeVersion::Legacy),
+			1 => Ok(TreeVersion::Tagged),
+			_ => Err(MerkleTreeError::UnknownVersion),
+		}
+	}
+}
+
+/// The configuration of the merkle trees of this crate, i.e., their height,
+/// and the hashing of their leaves and inner nodes.
+/// A `Tagged` tree needs a hash input of at least
+/// `1 + 2 * TAGGED_CHILD_BYTES` bytes.
+///
+/// Unlike `ark_crypto_primitives::merkle_tree::Config`, it carries the version
+/// of the hashing, so that a `Tagged` configuration implements only this
+/// trait, and cannot instantiate an ark tree, whose roots are `Legacy`.
+pub trait TreeHashing {
+	/// the height of the tree, which holds `2^(HEIGHT - 1)` leaves
+	const HEIGHT: usize;
+	/// the hash function
+	type H: FixedLengthCRH;
+	/// the version of the hashing
+	const VERSION: TreeVersion;
+}
+
+/// The digest of a node of a tree.
+pub type TreeDigest<P> = <<P as TreeHashing>::H as FixedLengthCRH>::Output;
+
+/// A root of the ledger tree, together with the version of its hashing,
+/// so that the roots of the legacy tree stay valid next to the tagged tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionedRoot {
+	/// the version of the tree
+	pub version: TreeVersion,
+	/// the root of the tree
+	pub root: LedgerMerkleTreeRoot,
+}
+
+impl MantaSerDes for VersionedRoot {
+	/// Serialize the root as `version (u8) | root`.
+	fn serialize<W: Write>(&self, mut writer: W) -> Result<(), MantaError> {
+		(self.version as u8).serialize(&mut writer)?;
+		self.root.serialize(&mut writer)?;
+		Ok(())
+	}
+
+	/// Deserialize a root. The version must be known, and the root a valid digest.
+	fn deserialize<R: Read>(mut reader: R) -> Result<Self, MantaError> {
+		let version = u8::deserialize(&mut reader)?;
+		let version = <TreeVersion as core::convert::TryFrom<u8>>::try_from(version)
+			.map_err(ark_crypto_primitives::Error::from)?;
+		let root = LedgerMerkleTreeRoot::deserialize(&mut reader)?;
+		Ok(Self { version, root })
+	}
+}
+
 pub trait MerkleTree {
 	type Param;
 	type Leaf;
@@ -30,6 +165,42 @@ pub trait MerkleTree {
 
 	/// get the root of the merkle tree
 	fn root(hash_param: Self::Param, payload: &[Self::Leaf]) -> Result<Self::Root, MantaError>;
+
+	/// get the root of the merkle tree of the given version;
+	/// by default, no version is supported
+	fn versioned_root(
+		_hash_param: Self::Param,
+		_leaves: &[Self::Leaf],
+		version: TreeVersion,
+	) -> Result<VersionedRoot, MantaError> {
+		Err(ark_crypto_primitives::Error::from(MerkleTreeError::UnsupportedVersion(version)).into())
+	}
+}
+
+/// The membership paths of a `MerkleTree`.
+pub trait MerkleTreePath: MerkleTree {
+	type Path;
+
+	/// generate the membership path of the leaf at `index`
+	fn membership_path(
+		hash_param: Self::Param,
+		leaves: &[Self::Leaf],
+		index: usize,
+	) -> Result<Self::Path, MantaError>;
+
+	/// serialize a membership path
+	fn path_to_bytes(path: &Self::Path) -> Result<Vec<u8>, MantaError>;
+
+	/// deserialize a membership path
+	fn path_from_bytes(bytes: &[u8]) -> Result<Self::Path, MantaError>;
+
+	/// verify a membership path of a leaf against the root of a tree
+	fn verify_path(
+		hash_param: Self::Param,
+		root: &Self::Root,
+		leaf: &Self::Leaf,
+		path: &Self::Path,
+	) -> Result<bool, MantaError>;
 }
 
 impl MerkleTree for MantaCrypto {
@@ -56,4 +227,133 @@ impl MerkleTree for MantaCrypto {
 		root.serialize(bytes.as_mut())?;
 		Ok(bytes)
 	}
+
+	/// The `Legacy` root is the root of `LedgerMerkleTree`, and the `Tagged`
+	/// root the one of `TaggedLedgerMerkleTree` over the same leaves.
+	fn versioned_root(
+		hash_param: Self::Param,
+		leaves: &[Self::Leaf],
+		version: TreeVersion,
+	) -> Result<VersionedRoot, MantaError> {
+		let root = match version {
+			TreeVersion::Legacy => Self::build_tree(hash_param, leaves)?.root(),
+			TreeVersion::Tagged => TaggedLedgerMerkleTree::from_leaves(hash_param, leaves)
+				.and_then(|tree| tree.root())
+				.map_err(ark_crypto_primitives::Error::from)?,
+		};
+		Ok(VersionedRoot { version, root })
+	}
+}
+
+impl MerkleTreePath for MantaCrypto {
+	type Path = LedgerMembershipPath;
+
+	/// Generate the path of the leaf at `index`, which is the witness of
+	/// `LedgerMembershipPathVar`.
+	fn membership_path(
+		hash_param: Self::Param,
+		leaves: &[Self::Leaf],
+		index: usize,
+	) -> Result<Self::Path, MantaError> {
+		let path = LedgerMembershipPath::from_leaves(&hash_param, leaves, index)
+			.map_err(ark_crypto_primitives::Error::from)?;
+		Ok(path)
+	}
+
+	fn path_to_bytes(path: &Self::Path) -> Result<Vec<u8>, MantaError> {
+		let mut bytes = Vec::new();
+		MantaSerDes::serialize(path, &mut bytes)?;
+		Ok(bytes)
+	}
+
+	fn path_from_bytes(bytes: &[u8]) -> Result<Self::Path, MantaError> {
+		<LedgerMembershipPath as MantaSerDes>::deserialize(bytes)
+	}
+
+	/// Verify the path natively; a malformed root is an error.
+	fn verify_path(
+		hash_param: Self::Param,
+		root: &Self::Root,
+		leaf: &Self::Leaf,
+		path: &Self::Path,
+	) -> Result<bool, MantaError> {
+		let root = LedgerMerkleTreeRoot::deserialize(root.as_ref())?;
+		let valid = path
+			.verify(&hash_param, &root, leaf)
+			.map_err(ark_crypto_primitives::Error::from)?;
+		Ok(valid)
+	}
+}
+
+/// The hash of a leaf of a tree: the tag of its version and the leaf bytes,
+/// padded with zeros to the input size of the hash.
+pub(crate) fn hash_leaf<P: TreeHashing, L: ToBytes>(
+	param: &<P::H as FixedLengthCRH>::Parameters,
+	leaf: &L,
+) -> Result<TreeDigest<P>, MerkleTreeError> {
+	let mut buffer: Vec<u8> = P::VERSION.leaf_tag().into_iter().collect();
+	leaf.write(&mut buffer)
+		.map_err(|_| MerkleTreeError::HashFailure)?;
+	hash_padded::<P::H>(param, buffer)
+}
+
+/// The hash of an inner node of a tree, from the tag of its version and the
+/// bytes of its children.
+pub(crate) fn hash_node<P: TreeHashing>(
+	param: &<P::H as FixedLengthCRH>::Parameters,
+	left: &TreeDigest<P>,
+	right: &TreeDigest<P>,
+) -> Result<TreeDigest<P>, MerkleTreeError> {
+	hash_children::<P::H>(param, P::VERSION.node_tag(), left, right)
+}
+
+/// The hash of an inner node, from the bytes of its children, with no tag.
+pub(crate) fn hash_inner_node<H: FixedLengthCRH>(
+	param: &H::Parameters,
+	left: &H::Output,
+	right: &H::Output,
+) -> Result<H::Output, MerkleTreeError> {
+	hash_children::<H>(param, None, left, right)
+}
+
+/// The hash of the bytes of two children, prefixed with the tag, if any;
+/// a tagged child is its first `TAGGED_CHILD_BYTES`.
+fn hash_children<H: FixedLengthCRH>(
+	param: &H::Parameters,
+	tag: Option<u8>,
+	left: &H::Output,
+	right: &H::Output,
+) -> Result<H::Output, MerkleTreeError> {
+	let mut buffer: Vec<u8> = tag.into_iter().collect();
+	for child in [left, right].iter() {
+		let mut bytes = Vec::new();
+		child
+			.write(&mut bytes)
+			.map_err(|_| MerkleTreeError::HashFailure)?;
+		if tag.is_some() {
+			bytes.truncate(TAGGED_CHILD_BYTES);
+		}
+		buffer.extend_from_slice(&bytes);
+	}
+	hash_padded::<H>(param, buffer)
+}
+
+/// The hash of an empty leaf, i.e., of an all-zero input.
+pub(crate) fn hash_empty<H: FixedLengthCRH>(
+	param: &H::Parameters,
+) -> Result<H::Output, MerkleTreeError> {
+	hash_padded::<H>(param, Vec::new())
+}
+
+/// Hash the input, zero-padded to exactly the input size of the hash.
+pub(crate) fn hash_padded<H: FixedLengthCRH>(
+	param: &H::Parameters,
+	mut input: Vec<u8>,
+) -> Result<H::Output, MerkleTreeError> {
+	let input_bytes = H::INPUT_SIZE_BITS / 8;
+	if input.len() > input_bytes {
+		return Err(MerkleTreeError::HashFailure);
+	}
+	input.resize(input_bytes, 0);
+	H::evaluate(param, &input).map_err(|_| MerkleTreeError::HashFailure)
 }
@@ -0,0 +1,254 @@
+// Copyright 2019-2021 Manta Network.
+// This file is part of manta-crypto.
+//
+// manta-crypto is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// manta-crypto is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with manta-crypto.  If not, see <http://www.gnu.org/licenses/>.
+
+//! This file implements note encryption to several recipients, e.g.,
+//! the receiver and an auditor, or the members of a multisig.
+//! The note is encrypted once under a random content key, which is then
+//! wrapped to every recipient under a key that is derived with `MantaKdf`
+//! from a single ephemeral exchange. The wire layout is
+//! ```text
+//! version (1) | ephemeral_pk (32) | n (1) | key_commitment (32)
+//!     | n * [view_tag (4) | wrapped_key (32) | wrap_tag (16)] | c | tag (16)
+//! ```
+//! The wrapped keys are authenticated together with
+//! `version | ephemeral_pk | n | key_commitment`, and the note together with
+//! every byte before it. AES-GCM does not commit to its key, so a sender
+//! could wrap a different content key to every recipient, under which the
+//! same `c | tag` decrypts to different notes. A recipient thus checks the
+//! unwrapped content key against `key_commitment = Blake2s(label | cek)`.
+use crate::{
+	note_encryption::{NOTE_PLAINTEXT_HEADER_BYTES, TAG_BYTES, VIEW_TAG_BYTES},
+	EciesError, MantaKdf, NotePlaintext, SecretBytes, MANTA_KDF_ENCRYPTION_LABEL,
+	MANTA_KDF_TAG_LABEL,
+};
+use aes_gcm::{
+	aead::{AeadInPlace, NewAead},
+	Aes256Gcm, Key, Nonce, Tag,
+};
+use ark_std::{
+	rand::{CryptoRng, RngCore},
+	vec::Vec,
+};
+use blake2::{Blake2s, Digest};
+use subtle::ConstantTimeEq;
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::Zeroize;
+
+/// The version of the multi-recipient note ciphertext wire layout.
+pub const MULTI_RECIPIENT_CIPHERTEXT_VERSION: u8 = 5;
+/// The maximum number of recipients of a note.
+pub const MULTI_RECIPIENT_MAX_RECIPIENTS: usize = u8::MAX as usize;
+
+const PREFIX_BYTES: usize = 1 + 32 + 1 + 32;
+const RECIPIENT_BYTES: usize = VIEW_TAG_BYTES + 32 + TAG_BYTES;
+
+/// The AES-GCM nonce; every content key and wrapping key is fresh.
+const MULTI_RECIPIENT_NONCE: [u8; 12] = [0u8; 12];
+/// The KDF context of multi-recipient note encryption.
+const MULTI_RECIPIENT_KDF_CONTEXT: &[u8] = b"manta note multi-recipient ecies";
+/// The label of the commitment to the content key.
+const CONTENT_KEY_COMMITMENT_LABEL: &[u8] = b"manta note content key commitment";
+
+/// Encrypt a note to every public key of `pks`.
+///
+/// # <weight>
+/// Steps:
+///     1. sample a random content key cek, and an ephemeral field element: ephemeral_sk
+///     2. set prefix = version | ephemeral_pk | n | Blake2s(label | cek)
+///     3. for every pk_i in pks:
+///         a. compute the shared secret ss_i = pk_i^ephemeral_sk
+///         b. set wrap_key_i = KDF(ss_i, ephemeral_pk, pk_i).derive("encryption key")
+///         c. set view_tag_i = KDF(ss_i, ephemeral_pk, pk_i).derive("tag key")[0..4]
+///         d. compute (w_i, w_tag_i) = aes_gcm_enc(cek, aad = prefix, wrap_key_i)
+///     4. compute (c, tag) = aes_gcm_enc(note, aad = header, cek),
+///        with header = prefix | [view_tag_i | w_i | w_tag_i]_i
+///     5. return [header | c | tag]
+/// # </weight>
+///
+/// # Panics
+/// Panics if `pks` is empty or has more than `MULTI_RECIPIENT_MAX_RECIPIENTS` keys,
+/// or if the memo is longer than `NOTE_MEMO_MAX_BYTES`.
+pub fn encrypt_note_to_many<R: RngCore + CryptoRng>(
+	pks: &[[u8; 32]],
+	note: &NotePlaintext,
+	rng: &mut R,
+) -> Vec<u8> {
+	assert!(
+		!pks.is_empty() && pks.len() <= MULTI_RECIPIENT_MAX_RECIPIENTS,
+		"invalid number of recipients {:?}",
+		pks.len()
+	);
+	let mut cek = SecretBytes::new([0u8; 32]);
+	rng.fill_bytes(cek.expose_mut());
+	let ephemeral_sk = StaticSecret::new(rng);
+	let ephemeral_pk = PublicKey::from(&ephemeral_sk);
+
+	let mut res = Vec::with_capacity(
+		PREFIX_BYTES
+			+ pks.len() * RECIPIENT_BYTES
+			+ NOTE_PLAINTEXT_HEADER_BYTES
+			+ note.memo.len()
+			+ TAG_BYTES,
+	);
+	res.push(MULTI_RECIPIENT_CIPHERTEXT_VERSION);
+	res.extend_from_slice(ephemeral_pk.as_bytes());
+	res.push(pks.len() as u8);
+	res.extend_from_slice(&content_key_commitment(&cek));
+	let prefix = res.clone();
+
+	for pk in pks {
+		res.extend_from_slice(&wrap_content_key(&ephemeral_sk, pk, &prefix, &cek));
+	}
+
+	let mut body = note.to_bytes();
+	let tag = Aes256Gcm::new(Key::from_slice(cek.expose()))
+		.encrypt_in_place_detached(Nonce::from_slice(&MULTI_RECIPIENT_NONCE), &res, &mut body)
+		.expect("a note never exceeds the AES-GCM length limit");
+	res.extend_from_slice(&body);
+	res.extend_from_slice(tag.as_slice());
+	res
+}
+
+/// Decrypt a multi-recipient note ciphertext with the secret key of any of its recipients.
+///
+/// # <weight>
+/// Steps:
+///     1. parse cipher as [prefix | [view_tag_i | w_i | w_tag_i]_i | c | tag],
+///        with prefix = version | ephemeral_pk | n | key_commitment, and check the version
+///     2. compute the shared secret ss = ephemeral_pk^sk, and pk = g^sk
+///     3. find the first i such that view_tag_i = KDF(ss, ephemeral_pk, pk).derive("tag key")[0..4]
+///        and cek = aes_gcm_dec(w_i, w_tag_i, aad = prefix, wrap_key) succeeds,
+///        with wrap_key = KDF(ss, ephemeral_pk, pk).derive("encryption key"), or fail
+///     4. check that Blake2s(label | cek) = key_commitment, or fail
+///     5. compute note = aes_gcm_dec(c, tag, aad = header, cek), or fail
+///     6. return note
+/// # </weight>
+pub fn decrypt_multi_recipient_note(
+	sk: &SecretBytes,
+	cipher: &[u8],
+) -> Result<NotePlaintext, EciesError> {
+	if cipher.len() < PREFIX_BYTES {
+		return Err(EciesError::MalformedCiphertext);
+	}
+	if cipher[0] != MULTI_RECIPIENT_CIPHERTEXT_VERSION {
+		return Err(EciesError::UnsupportedVersion(cipher[0]));
+	}
+	let header_bytes = PREFIX_BYTES + cipher[PREFIX_BYTES - 1] as usize * RECIPIENT_BYTES;
+	if cipher.len() < header_bytes + NOTE_PLAINTEXT_HEADER_BYTES + TAG_BYTES {
+		return Err(EciesError::MalformedCiphertext);
+	}
+	let (header, rest) = cipher.split_at(header_bytes);
+	let (prefix, recipients) = header.split_at(PREFIX_BYTES);
+	let (body, tag) = rest.split_at(rest.len() - TAG_BYTES);
+
+	let mut ephemeral_pk = [0u8; 32];
+	ephemeral_pk.copy_from_slice(&prefix[1..33]);
+	let sk = StaticSecret::from(*sk.expose());
+	let pk = PublicKey::from(&sk);
+	let shared_secret = sk.diffie_hellman(&PublicKey::from(ephemeral_pk));
+	let kdf = MantaKdf::new(
+		MULTI_RECIPIENT_KDF_CONTEXT,
+		shared_secret.as_bytes(),
+		&ephemeral_pk,
+		pk.as_bytes(),
+	);
+	let view_tag = kdf.derive(MANTA_KDF_TAG_LABEL);
+	let wrap_key = Aes256Gcm::new(Key::from_slice(
+		kdf.derive(MANTA_KDF_ENCRYPTION_LABEL).expose(),
+	));
+
+	let cek = recipients
+		.chunks(RECIPIENT_BYTES)
+		.filter(|entry| {
+			entry[0..VIEW_TAG_BYTES]
+				.ct_eq(&view_tag.expose()[0..VIEW_TAG_BYTES])
+				.into()
+		})
+		.find_map(|entry| {
+			let mut cek = SecretBytes::new([0u8; 32]);
+			cek.expose_mut()
+				.copy_from_slice(&entry[VIEW_TAG_BYTES..VIEW_TAG_BYTES + 32]);
+			wrap_key
+				.decrypt_in_place_detached(
+					Nonce::from_slice(&MULTI_RECIPIENT_NONCE),
+					prefix,
+					cek.expose_mut(),
+					Tag::from_slice(&entry[VIEW_TAG_BYTES + 32..]),
+				)
+				.ok()
+				.map(|_| cek)
+		})
+		.ok_or(EciesError::AuthenticationFailure)?;
+	if !bool::from(content_key_commitment(&cek)[..].ct_eq(&prefix[PREFIX_BYTES - 32..])) {
+		return Err(EciesError::AuthenticationFailure);
+	}
+
+	let mut body = body.to_vec();
+	Aes256Gcm::new(Key::from_slice(cek.expose()))
+		.decrypt_in_place_detached(
+			Nonce::from_slice(&MULTI_RECIPIENT_NONCE),
+			header,
+			&mut body,
+			Tag::from_slice(tag),
+		)
+		.map_err(|_| EciesError::AuthenticationFailure)?;
+
+	let note = NotePlaintext::from_bytes(&body);
+	body.zeroize();
+	note
+}
+
+/// The commitment to a content key, which AES-GCM does not provide.
+pub(crate) fn content_key_commitment(cek: &SecretBytes) -> [u8; 32] {
+	let mut hasher = Blake2s::new();
+	hasher.update(CONTENT_KEY_COMMITMENT_LABEL);
+	hasher.update(cek.expose());
+	let mut res = [0u8; 32];
+	res.copy_from_slice(hasher.finalize().as_slice());
+	res
+}
+
+/// The entry `view_tag | wrapped_key | wrap_tag` of the recipient `pk`.
+pub(crate) fn wrap_content_key(
+	ephemeral_sk: &StaticSecret,
+	pk: &[u8; 32],
+	prefix: &[u8],
+	cek: &SecretBytes,
+) -> Vec<u8> {
+	let ephemeral_pk = PublicKey::from(ephemeral_sk);
+	let shared_secret = ephemeral_sk.diffie_hellman(&PublicKey::from(*pk));
+	let kdf = MantaKdf::new(
+		MULTI_RECIPIENT_KDF_CONTEXT,
+		shared_secret.as_bytes(),
+		ephemeral_pk.as_bytes(),
+		pk,
+	);
+	let wrap_key = kdf.derive(MANTA_KDF_ENCRYPTION_LABEL);
+	let mut wrapped_key = *cek.expose();
+	let wrap_tag = Aes256Gcm::new(Key::from_slice(wrap_key.expose()))
+		.encrypt_in_place_detached(
+			Nonce::from_slice(&MULTI_RECIPIENT_NONCE),
+			prefix,
+			&mut wrapped_key,
+		)
+		.expect("32 bytes never exceed the AES-GCM length limit");
+
+	let mut entry = Vec::with_capacity(RECIPIENT_BYTES);
+	entry.extend_from_slice(&kdf.derive(MANTA_KDF_TAG_LABEL).expose()[0..VIEW_TAG_BYTES]);
+	entry.extend_from_slice(&wrapped_key);
+	entry.extend_from_slice(wrap_tag.as_slice());
+	entry
+}
@@ -0,0 +1,323 @@
+// Copyright 2019-2021 Manta Network.
+// This file is part of manta-crypto.
+//
+// manta-crypto is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// manta-crypto is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with manta-crypto.  If not, see <http://www.gnu.org/licenses/>.
+
+//! This file implements a merkle tree over a pluggable storage of nodes.
+//!
+//! The tree only stores complete nodes, i.e., the roots of subtrees whose
+//! leaves are all appended, so that a stored node never changes. Every
+//! other node is recomputed from the complete nodes below it. The number
+//! of leaves is the length of the prefix of stored leaves, so that a tree
+//! is reopened from its storage with a binary search, and otherwise only
+//! reads the nodes that it needs.
+//!
+//! The nodes of an append are stored from the top down, then flushed, and
+//! the leaf is stored last, so that an interrupted append, e.g., by a crash
+//! or a power loss, is not part of the reopened tree. The leaf itself is
+//! durable once the storage is flushed again, e.g., by a later append.
+use crate::{
+	incremental_merkle_tree::Frontier,
+	merkle_tree::{hash_empty, hash_leaf, hash_node, MerkleTreeError, TreeDigest, TreeHashing},
+	IncrementalMerkleTree, MembershipPath,
+};
+use ark_crypto_primitives::FixedLengthCRH;
+use ark_ff::ToBytes;
+use ark_std::{collections::BTreeMap, vec, vec::Vec};
+
+/// A storage of merkle tree nodes, by level and index. The leaves are at level 0.
+pub trait NodeStorage<D> {
+	/// get the node at `(level, index)`, if it is stored
+	fn get(&self, level: usize, index: usize) -> Result<Option<D>, MerkleTreeError>;
+
+	/// store the node at `(level, index)`
+	fn put(&mut self, level: usize, index: usize, node: &D) -> Result<(), MerkleTreeError>;
+
+	/// make the stored nodes durable, e.g., on the disk
+	fn flush(&mut self) -> Result<(), MerkleTreeError> {
+		Ok(())
+	}
+}
+
+/// A storage of nodes in memory.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryNodeStorage<D> {
+	nodes: BTreeMap<(usize, usize), D>,
+}
+
+impl<D: Clone> NodeStorage<D> for MemoryNodeStorage<D> {
+	fn get(&self, level: usize, index: usize) -> Result<Option<D>, MerkleTreeError> {
+		Ok(self.nodes.get(&(level, index)).cloned())
+	}
+
+	fn put(&mut self, level: usize, index: usize, node: &D) -> Result<(), MerkleTreeError> {
+		self.nodes.insert((level, index), node.clone());
+		Ok(())
+	}
+}
+
+/// A merkle tree whose nodes live in a `NodeStorage`.
+/// Its roots are those of `ark_crypto_primitives::MerkleTree`.
+pub struct StoredMerkleTree<P: TreeHashing, S: NodeStorage<TreeDigest<P>>> {
+	param: <P::H as FixedLengthCRH>::Parameters,
+	storage: S,
+	/// the number of leaves
+	size: usize,
+	/// `empty[l]` is the root of a subtree of `2^l` empty leaves
+	empty: Vec<TreeDigest<P>>,
+}
+
+impl<P: TreeHashing, S: NodeStorage<TreeDigest<P>>> StoredMerkleTree<P, S> {
+	/// Open the tree of the storage; an empty storage holds an empty tree.
+	pub fn open(
+		param: <P::H as FixedLengthCRH>::Parameters,
+		storage: S,
+	) -> Result<Self, MerkleTreeError> {
+		let mut empty = vec![hash_empty::<P::H>(&param)?];
+		for level in 1..P::HEIGHT {
+			let node = hash_node::<P>(&param, &empty[level - 1], &empty[level - 1])?;
+			empty.push(node);
+		}
+
+		// the leaves are a prefix of level 0: find its length
+		let (mut low, mut high) = (0, IncrementalMerkleTree::<P>::CAPACITY);
+		while low < high {
+			let mid = low + (high - low) / 2;
+			if storage.get(0, mid)?.is_some() {
+				low = mid + 1;
+			} else {
+				high = mid;
+			}
+		}
+		Ok(Self {
+			param,
+			storage,
+			size: low,
+			empty,
+		})
+	}
+
+	/// The number of leaves of the tree.
+	pub fn len(&self) -> usize {
+		self.size
+	}
+
+	/// Whether the tree has no leaves.
+	pub fn is_empty(&self) -> bool {
+		self.size == 0
+	}
+
+	/// The storage of the tree.
+	pub fn storage(&self) -> &S {
+		&self.storage
+	}
+
+	/// Close the tree, and return its storage.
+	pub fn into_storage(self) -> S {
+		self.storage
+	}
+
+	/// Append a leaf to the tree, and return its index.
+	///
+	/// # <weight>
+	/// Steps:
+	///     1. set node = H(leaf), and index = the number of leaves
+	///     2. while index is a right child:
+	///         a. set node = H(stored left sibling, node)
+	///         b. set index = index / 2, one level up
+	///     3. store the new complete nodes from the top down, and flush them
+	///     4. store the leaf
+	/// # </weight>
+	pub fn append<L: ToBytes>(&mut self, leaf: &L) -> Result<usize, MerkleTreeError> {
+		if self.size == IncrementalMerkleTree::<P>::CAPACITY {
+			return Err(MerkleTreeError::TreeFull);
+		}
+		let mut nodes = vec![hash_leaf::<P, _>(&self.param, leaf)?];
+		let mut index = self.size;
+		while index & 1 == 1 {
+			let left = self.node(nodes.len() - 1, index - 1)?;
+			let node = hash_node::<P>(&self.param, &left, &nodes[nodes.len() - 1])?;
+			nodes.push(node);
+			index >>= 1;
+		}
+		for (level, node) in nodes.iter().enumerate().skip(1).rev() {
+			self.storage.put(level, self.size >> level, node)?;
+		}
+		// the leaf must never reach the storage before the nodes above it
+		if nodes.len() > 1 {
+			self.storage.flush()?;
+		}
+		self.storage.put(0, self.size, &nodes[0])?;
+		self.size += 1;
+		Ok(self.size - 1)
+	}
+
+	/// The root of the tree.
+	pub fn root(&self) -> Result<TreeDigest<P>, MerkleTreeError> {
+		let depth = self.size.max(1).next_power_of_two().trailing_zeros() as usize;
+		let mut root = self.subtree_root(depth, 0)?;
+		for _ in 0..IncrementalMerkleTree::<P>::padding(depth) {
+			root = hash_node::<P>(&self.param, &root, &self.empty[0])?;
+		}
+		Ok(root)
+	}
+
+	/// The membership path of the leaf at `index`.
+	pub fn path(&self, index: usize) -> Result<MembershipPath<P>, MerkleTreeError> {
+		if index >= self.size {
+			return Err(MerkleTreeError::InvalidLeafIndex);
+		}
+		let depth = self.size.max(1).next_power_of_two().trailing_zeros() as usize;
+		let mut siblings = Vec::with_capacity(P::HEIGHT);
+		for level in 0..depth {
+			siblings.push(self.subtree_root(level, (index >> level) ^ 1)?);
+		}
+		let padding = IncrementalMerkleTree::<P>::padding(depth);
+		siblings.extend(ark_std::iter::repeat(self.empty[0].clone()).take(padding));
+		Ok(MembershipPath { index, siblings })
+	}
+
+	/// The root of the subtree at `(level, index)`, where the missing leaves are empty.
+	fn subtree_root(&self, level: usize, index: usize) -> Result<TreeDigest<P>, MerkleTreeError> {
+		// the frontier of the subtree is made of the complete nodes that end at its last leaf
+		let start = index << level;
+		let count = self.size.saturating_sub(start).min(1 << level);
+		let mut frontier = Frontier::<P>::new();
+		frontier.size = count;
+		for m in 0..=level {
+			if (count >> m) & 1 == 1 {
+				frontier.filled[m] = Some(self.node(m, ((start + count) >> m) - 1)?);
+			}
+		}
+		frontier.subtree_root(&self.param, &self.empty, level)
+	}
+
+	/// A complete node, which must be stored.
+	fn node(&self, level: usize, index: usize) -> Result<TreeDigest<P>, MerkleTreeError> {
+		self.storage
+			.get(level, index)?
+			.ok_or(MerkleTreeError::StorageFailure)
+	}
+}
+
+#[cfg(feature = "std")]
+pub use self::file::FileNodeStorage;
+
+#[cfg(feature = "std")]
+mod file {
+	use super::*;
+	use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+	use ark_std::marker::PhantomData;
+	use std::{
+		fs::{self, File, OpenOptions},
+		io::{self, Read, Seek, SeekFrom, Write},
+		path::Path,
+		vec::Vec,
+	};
+
+	/// A storage of the nodes of a tree of config `P` in a directory, with a
+	/// file per level. A file is an array of records `present (1) | node`,
+	/// by index, where the node is written before its `present` flag.
+	/// A record that was never written reads as absent, and so does the last
+	/// record of a file if it is torn, e.g., by a crash.
+	pub struct FileNodeStorage<P: TreeHashing> {
+		files: Vec<File>,
+		/// the length of a serialized node
+		node_bytes: usize,
+		_config: PhantomData<P>,
+	}
+
+	impl<P: TreeHashing> FileNodeStorage<P> {
+		/// Open the storage of a tree in `dir`, and create it if it does not exist.
+		pub fn open<Q: AsRef<Path>>(dir: Q) -> io::Result<Self> {
+			fs::create_dir_all(&dir)?;
+			let files = (0..P::HEIGHT)
+				.map(|level| {
+					OpenOptions::new()
+						.read(true)
+						.write(true)
+						.create(true)
+						.open(dir.as_ref().join(std::format!("level_{}.bin", level)))
+				})
+				.collect::<io::Result<Vec<_>>>()?;
+			Ok(Self {
+				files,
+				node_bytes: TreeDigest::<P>::default().serialized_size(),
+				_config: PhantomData,
+			})
+		}
+
+		fn file(&self, level: usize) -> Result<&File, MerkleTreeError> {
+			self.files.get(level).ok_or(MerkleTreeError::StorageFailure)
+		}
+	}
+
+	impl<P: TreeHashing> NodeStorage<TreeDigest<P>> for FileNodeStorage<P> {
+		fn get(
+			&self,
+			level: usize,
+			index: usize,
+		) -> Result<Option<TreeDigest<P>>, MerkleTreeError> {
+			let mut file = self.file(level)?;
+			let offset = (index * (1 + self.node_bytes)) as u64;
+			let len = file
+				.metadata()
+				.map_err(|_| MerkleTreeError::StorageFailure)?
+				.len();
+			if offset + 1 + self.node_bytes as u64 > len {
+				return Ok(None);
+			}
+			let mut record = vec![0u8; 1 + self.node_bytes];
+			file.seek(SeekFrom::Start(offset))
+				.and_then(|_| file.read_exact(&mut record))
+				.map_err(|_| MerkleTreeError::StorageFailure)?;
+			if record[0] == 0 {
+				return Ok(None);
+			}
+			match TreeDigest::<P>::deserialize(&record[1..]) {
+				Ok(node) => Ok(Some(node)),
+				// a torn write of the last record
+				Err(_) if offset + record.len() as u64 == len => Ok(None),
+				Err(_) => Err(MerkleTreeError::StorageFailure),
+			}
+		}
+
+		/// Write the node, and then its `present` flag.
+		fn put(
+			&mut self,
+			level: usize,
+			index: usize,
+			node: &TreeDigest<P>,
+		) -> Result<(), MerkleTreeError> {
+			let mut bytes = Vec::with_capacity(self.node_bytes);
+			node.serialize(&mut bytes)
+				.map_err(|_| MerkleTreeError::StorageFailure)?;
+			let mut file = self.file(level)?;
+			let offset = (index * (1 + self.node_bytes)) as u64;
+			file.seek(SeekFrom::Start(offset + 1))
+				.and_then(|_| file.write_all(&bytes))
+				.and_then(|_| file.seek(SeekFrom::Start(offset)))
+				.and_then(|_| file.write_all(&[1u8]))
+				.map_err(|_| MerkleTreeError::StorageFailure)
+		}
+
+		/// Flush the written nodes to the disk.
+		fn flush(&mut self) -> Result<(), MerkleTreeError> {
+			self.files
+				.iter()
+				.try_for_each(|file| file.sync_data())
+				.map_err(|_| MerkleTreeError::StorageFailure)
+		}
+	}
+}
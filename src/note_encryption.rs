@@ -0,0 +1,427 @@
+// Copyright 2019-2021 Manta Network.
+// This file is part of manta-crypto.
+//
+// manta-crypto is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// manta-crypto is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with manta-crypto.  If not, see <http://www.gnu.org/licenses/>.
+
+//! This file implements ECIES for notes, i.e., everything that a receiver
+//! needs to spend a coin: the value, the asset id, the commitment randomness,
+//! rho and a memo of arbitrary length.
+//!
+//! Wire layout of a note ciphertext, version 2:
+//! ```text
+//! version (1) | ephemeral_pk (32) | view_tag (4) | c (len(plaintext)) | tag (16)
+//! ```
+//! The view tag lets a receiver discard most notes that are not theirs
+//! without running AES-GCM over the whole ciphertext.
+//!
+//! A sender can also attach an outgoing ciphertext, encrypted under its
+//! outgoing viewing key, to recover the notes it sent, e.g., after a
+//! restore from seed.
+//! Layout of a note plaintext, integers in little-endian:
+//! ```text
+//! value (8) | asset_id (8) | randomness (32) | rho (32) | memo_len (2) | memo (memo_len)
+//! ```
+use crate::{
+	AssetId, Ecies, EciesError, MantaKdf, Secret, SecretBytes, MANTA_KDF_ENCRYPTION_LABEL,
+	MANTA_KDF_TAG_LABEL,
+};
+use aes_gcm::{
+	aead::{AeadInPlace, NewAead},
+	Aes256Gcm, Key, Nonce, Tag,
+};
+use ark_std::{
+	rand::{CryptoRng, RngCore},
+	vec::Vec,
+};
+use blake2::{Blake2s, Digest};
+use subtle::ConstantTimeEq;
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::Zeroize;
+
+/// The version of the note ciphertext wire layout.
+pub const NOTE_CIPHERTEXT_VERSION: u8 = 2;
+/// The maximum length of a memo.
+pub const NOTE_MEMO_MAX_BYTES: usize = u16::MAX as usize;
+/// The length of an outgoing ciphertext: ephemeral_sk (32) | pk (32) | tag (16).
+pub const OUTGOING_CIPHERTEXT_BYTES: usize = 32 + 32 + 16;
+
+const VERSION_BYTES: usize = 1;
+const EPHEMERAL_PK_BYTES: usize = 32;
+pub(crate) const VIEW_TAG_BYTES: usize = 4;
+const HEADER_BYTES: usize = VERSION_BYTES + EPHEMERAL_PK_BYTES + VIEW_TAG_BYTES;
+pub(crate) const TAG_BYTES: usize = 16;
+/// The length of a note plaintext without its memo.
+pub(crate) const NOTE_PLAINTEXT_HEADER_BYTES: usize = 8 + 8 + 32 + 32 + 2;
+
+/// The AES-GCM nonce; every note is encrypted under a fresh key.
+const NOTE_NONCE: [u8; 12] = [0u8; 12];
+/// The KDF context of note encryption.
+const NOTE_KDF_CONTEXT: &[u8] = b"manta note ecies";
+/// The KDF context of outgoing ciphertexts.
+const OUTGOING_KDF_CONTEXT: &[u8] = b"manta note outgoing";
+
+/// The plaintext of a note.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotePlaintext {
+	/// the value of the coin
+	pub value: u64,
+	/// the asset of the coin
+	pub asset_id: AssetId,
+	/// the randomness of the coin commitment
+	pub randomness: SecretBytes,
+	/// the rho of the coin
+	pub rho: [u8; 32],
+	/// a memo of at most `NOTE_MEMO_MAX_BYTES` bytes
+	pub memo: Vec<u8>,
+}
+
+impl NotePlaintext {
+	/// Serialize the note plaintext.
+	///
+	/// # Panics
+	/// Panics if the memo is longer than `NOTE_MEMO_MAX_BYTES`.
+	pub fn to_bytes(&self) -> Vec<u8> {
+		assert!(
+			self.memo.len() <= NOTE_MEMO_MAX_BYTES,
+			"memo of {:?} bytes is too long",
+			self.memo.len()
+		);
+		[
+			self.value.to_le_bytes().as_ref(),
+			self.asset_id.to_le_bytes().as_ref(),
+			self.randomness.expose().as_ref(),
+			self.rho.as_ref(),
+			(self.memo.len() as u16).to_le_bytes().as_ref(),
+			self.memo.as_ref(),
+		]
+		.concat()
+	}
+
+	/// Deserialize a note plaintext.
+	pub fn from_bytes(bytes: &[u8]) -> Result<Self, EciesError> {
+		if bytes.len() < NOTE_PLAINTEXT_HEADER_BYTES {
+			return Err(EciesError::MalformedPlaintext);
+		}
+		let mut value = [0u8; 8];
+		value.copy_from_slice(&bytes[0..8]);
+		let mut asset_id = [0u8; 8];
+		asset_id.copy_from_slice(&bytes[8..16]);
+		let mut randomness = SecretBytes::new([0u8; 32]);
+		randomness.expose_mut().copy_from_slice(&bytes[16..48]);
+		let mut rho = [0u8; 32];
+		rho.copy_from_slice(&bytes[48..80]);
+		let mut memo_len = [0u8; 2];
+		memo_len.copy_from_slice(&bytes[80..82]);
+
+		let memo = &bytes[NOTE_PLAINTEXT_HEADER_BYTES..];
+		if memo.len() != u16::from_le_bytes(memo_len) as usize {
+			return Err(EciesError::MalformedPlaintext);
+		}
+
+		Ok(Self {
+			value: u64::from_le_bytes(value),
+			asset_id: u64::from_le_bytes(asset_id),
+			randomness,
+			rho,
+			memo: memo.to_vec(),
+		})
+	}
+}
+
+/// ECIES for note plaintexts, with variable-length ciphertexts.
+pub struct NoteEncryption;
+
+impl Ecies for NoteEncryption {
+	type PublicKey = [u8; 32];
+	type PrivateKey = SecretBytes;
+	type Message = NotePlaintext;
+	type Ciphertext = Vec<u8>;
+	type Error = EciesError;
+
+	/// Generate a pair of keys
+	fn keygen<R: RngCore + CryptoRng>(rng: &mut R) -> (Self::PublicKey, Self::PrivateKey) {
+		let sk = StaticSecret::new(rng);
+		let pk = PublicKey::from(&sk);
+		(pk.to_bytes(), SecretBytes::new(sk.to_bytes()))
+	}
+
+	/// Encrypt the note under the input public key.
+	///
+	/// # <weight>
+	/// Steps:
+	///     1. sample a random, ephemeral field element: ephemeral_sk
+	///     2. compute the group element ephemeral_pk
+	///     3. compute the shared secret ss = pk^ephemeral_sk
+	///     4. set aes_key = KDF(ss, ephemeral_pk, pk).derive("encryption key")
+	///     5. set view_tag = KDF(ss, ephemeral_pk, pk).derive("tag key")[0..4]
+	///     6. compute (c, tag) = aes_gcm_enc(note, aad = version | ephemeral_pk | view_tag, aes_key)
+	///     7. return [version | ephemeral_pk | view_tag | c | tag]
+	/// # </weight>
+	///
+	/// # Panics
+	/// Panics if the memo is longer than `NOTE_MEMO_MAX_BYTES`.
+	fn encrypt<R: RngCore + CryptoRng>(
+		pk: &Self::PublicKey,
+		message: &Self::Message,
+		rng: &mut R,
+	) -> Self::Ciphertext {
+		seal_note(pk, &StaticSecret::new(rng), message)
+	}
+
+	/// Decrypt a note ciphertext with a secret key.
+	///
+	/// # <weight>
+	/// Steps:
+	///     1. parse cipher as [version | ephemeral_pk | view_tag | c | tag], and check the version
+	///     2. compute the shared secret ss = ephemeral_pk^sk
+	///     3. check view_tag = KDF(ss, ephemeral_pk, pk).derive("tag key")[0..4], with pk = g^sk
+	///     4. set aes_key = KDF(ss, ephemeral_pk, pk).derive("encryption key")
+	///     5. compute note = aes_gcm_dec(c, tag, aad = version | ephemeral_pk | view_tag, aes_key), or fail
+	///     6. return note
+	/// # </weight>
+	fn decrypt(
+		sk: &Self::PrivateKey,
+		cipher: &Self::Ciphertext,
+	) -> Result<Self::Message, Self::Error> {
+		decrypt_note(sk, cipher)
+	}
+}
+
+/// Decrypt a note ciphertext with a secret key, see `<NoteEncryption as Ecies>::decrypt`.
+/// A view tag mismatch fails with `AuthenticationFailure` before AES-GCM runs.
+pub(crate) fn decrypt_note(sk: &SecretBytes, cipher: &[u8]) -> Result<NotePlaintext, EciesError> {
+	let parsed = NoteCiphertext::parse(cipher)?;
+	let sk = StaticSecret::from(*sk.expose());
+	let shared_secret = sk.diffie_hellman(&parsed.ephemeral_pk);
+	open_note(
+		&parsed,
+		shared_secret.as_bytes(),
+		PublicKey::from(&sk).as_bytes(),
+	)
+}
+
+/// A note ciphertext, split along its wire layout.
+struct NoteCiphertext<'a> {
+	/// version | ephemeral_pk | view_tag
+	header: &'a [u8],
+	ephemeral_pk: PublicKey,
+	body: &'a [u8],
+	tag: &'a [u8],
+}
+
+impl<'a> NoteCiphertext<'a> {
+	fn parse(cipher: &'a [u8]) -> Result<Self, EciesError> {
+		if cipher.len() < HEADER_BYTES + NOTE_PLAINTEXT_HEADER_BYTES + TAG_BYTES {
+			return Err(EciesError::MalformedCiphertext);
+		}
+		if cipher[0] != NOTE_CIPHERTEXT_VERSION {
+			return Err(EciesError::UnsupportedVersion(cipher[0]));
+		}
+		let (header, rest) = cipher.split_at(HEADER_BYTES);
+		let (body, tag) = rest.split_at(rest.len() - TAG_BYTES);
+
+		let mut pk_bytes = [0u8; 32];
+		pk_bytes.copy_from_slice(&header[VERSION_BYTES..VERSION_BYTES + EPHEMERAL_PK_BYTES]);
+
+		Ok(Self {
+			header,
+			ephemeral_pk: PublicKey::from(pk_bytes),
+			body,
+			tag,
+		})
+	}
+}
+
+/// Encrypt a note to `pk` with a given ephemeral secret.
+fn seal_note(pk: &[u8; 32], ephemeral_sk: &StaticSecret, note: &NotePlaintext) -> Vec<u8> {
+	let ephemeral_pk = PublicKey::from(ephemeral_sk);
+	let shared_secret = ephemeral_sk.diffie_hellman(&PublicKey::from(*pk));
+	let kdf = MantaKdf::new(
+		NOTE_KDF_CONTEXT,
+		shared_secret.as_bytes(),
+		ephemeral_pk.as_bytes(),
+		pk,
+	);
+
+	let mut header = Vec::with_capacity(
+		HEADER_BYTES + NOTE_PLAINTEXT_HEADER_BYTES + note.memo.len() + TAG_BYTES,
+	);
+	header.push(NOTE_CIPHERTEXT_VERSION);
+	header.extend_from_slice(ephemeral_pk.as_bytes());
+	seal_with_kdf(&kdf, header, note)
+}
+
+/// Decrypt a parsed note ciphertext with the shared secret of the exchange
+/// between its ephemeral key and the recipient `pk`.
+fn open_note(
+	cipher: &NoteCiphertext,
+	shared_secret: &[u8; 32],
+	pk: &[u8; 32],
+) -> Result<NotePlaintext, EciesError> {
+	let kdf = MantaKdf::new(
+		NOTE_KDF_CONTEXT,
+		shared_secret,
+		cipher.ephemeral_pk.as_bytes(),
+		pk,
+	);
+	open_with_kdf(&kdf, cipher.header, cipher.body, cipher.tag)
+}
+
+/// Append the view tag to `header`, then the note encrypted under `kdf`
+/// with the whole header as associated data, and its AES-GCM tag.
+pub(crate) fn seal_with_kdf(kdf: &MantaKdf, mut header: Vec<u8>, note: &NotePlaintext) -> Vec<u8> {
+	header.extend_from_slice(&kdf.derive(MANTA_KDF_TAG_LABEL).expose()[0..VIEW_TAG_BYTES]);
+
+	let aes_key = kdf.derive(MANTA_KDF_ENCRYPTION_LABEL);
+	let cipher = Aes256Gcm::new(Key::from_slice(aes_key.expose()));
+	let mut body = note.to_bytes();
+	let tag = cipher
+		.encrypt_in_place_detached(Nonce::from_slice(&NOTE_NONCE), &header, &mut body)
+		.expect("a note never exceeds the AES-GCM length limit");
+
+	let mut res = header;
+	res.extend_from_slice(&body);
+	res.extend_from_slice(tag.as_slice());
+	res
+}
+
+/// Decrypt a note that was sealed with `seal_with_kdf`.
+/// The view tag is the end of `header`, and is checked before AES-GCM runs.
+pub(crate) fn open_with_kdf(
+	kdf: &MantaKdf,
+	header: &[u8],
+	body: &[u8],
+	tag: &[u8],
+) -> Result<NotePlaintext, EciesError> {
+	let view_tag = kdf.derive(MANTA_KDF_TAG_LABEL);
+	if !bool::from(
+		header[header.len() - VIEW_TAG_BYTES..].ct_eq(&view_tag.expose()[0..VIEW_TAG_BYTES]),
+	) {
+		return Err(EciesError::AuthenticationFailure);
+	}
+
+	let aes_key = kdf.derive(MANTA_KDF_ENCRYPTION_LABEL);
+	let aes_gcm = Aes256Gcm::new(Key::from_slice(aes_key.expose()));
+	let mut body = body.to_vec();
+	aes_gcm
+		.decrypt_in_place_detached(
+			Nonce::from_slice(&NOTE_NONCE),
+			header,
+			&mut body,
+			Tag::from_slice(tag),
+		)
+		.map_err(|_| EciesError::AuthenticationFailure)?;
+
+	let note = NotePlaintext::from_bytes(&body);
+	body.zeroize();
+	note
+}
+
+//=======================
+// outgoing viewing keys
+//=======================
+
+/// Encrypt a note to `pk`, together with an outgoing ciphertext that lets
+/// the holder of the outgoing viewing key `ovk` recover the note later.
+///
+/// # <weight>
+/// Steps:
+///     1. sample a random, ephemeral field element: ephemeral_sk
+///     2. compute cipher = <NoteEncryption as Ecies>::encrypt(pk, note) with ephemeral_sk
+///     3. set out_key = KDF(ovk, ephemeral_pk, blake2s(cipher)).derive("encryption key")
+///     4. compute (out_c, out_tag) = aes_gcm_enc(ephemeral_sk | pk, aad = cipher, out_key)
+///     5. return (cipher, [out_c | out_tag])
+/// # </weight>
+///
+/// # Panics
+/// Panics if the memo is longer than `NOTE_MEMO_MAX_BYTES`.
+pub fn encrypt_note_with_ovk<R: RngCore + CryptoRng>(
+	pk: &[u8; 32],
+	ovk: &SecretBytes,
+	note: &NotePlaintext,
+	rng: &mut R,
+) -> (Vec<u8>, [u8; OUTGOING_CIPHERTEXT_BYTES]) {
+	let ephemeral_sk = StaticSecret::new(rng);
+	let ephemeral_pk = PublicKey::from(&ephemeral_sk);
+	let cipher = seal_note(pk, &ephemeral_sk, note);
+
+	let out_key = outgoing_key(ovk, ephemeral_pk.as_bytes(), &cipher);
+	let aes_gcm = Aes256Gcm::new(Key::from_slice(out_key.expose()));
+	let mut out_body = [0u8; 64];
+	out_body[0..32].copy_from_slice(&ephemeral_sk.to_bytes());
+	out_body[32..64].copy_from_slice(pk);
+	let out_tag = aes_gcm
+		.encrypt_in_place_detached(Nonce::from_slice(&NOTE_NONCE), &cipher, &mut out_body)
+		.expect("64 bytes never exceed the AES-GCM length limit");
+
+	let mut out_cipher = [0u8; OUTGOING_CIPHERTEXT_BYTES];
+	out_cipher[0..64].copy_from_slice(&out_body);
+	out_cipher[64..].copy_from_slice(out_tag.as_slice());
+	(cipher, out_cipher)
+}
+
+/// Recover a note that was sent with `encrypt_note_with_ovk`,
+/// from the note ciphertext and the outgoing ciphertext.
+///
+/// # <weight>
+/// Steps:
+///     1. parse ephemeral_pk from cipher
+///     2. set out_key = KDF(ovk, ephemeral_pk, blake2s(cipher)).derive("encryption key")
+///     3. compute ephemeral_sk | pk = aes_gcm_dec(out_c, out_tag, aad = cipher, out_key), or fail
+///     4. check ephemeral_pk = g^ephemeral_sk
+///     5. return the note of cipher, decrypted with ss = pk^ephemeral_sk
+/// # </weight>
+pub fn recover_outgoing_note(
+	ovk: &SecretBytes,
+	cipher: &[u8],
+	out_cipher: &[u8; OUTGOING_CIPHERTEXT_BYTES],
+) -> Result<NotePlaintext, EciesError> {
+	let parsed = NoteCiphertext::parse(cipher)?;
+
+	let out_key = outgoing_key(ovk, parsed.ephemeral_pk.as_bytes(), cipher);
+	let aes_gcm = Aes256Gcm::new(Key::from_slice(out_key.expose()));
+	let mut out_body = Secret::new([0u8; 64]);
+	out_body.expose_mut().copy_from_slice(&out_cipher[0..64]);
+	aes_gcm
+		.decrypt_in_place_detached(
+			Nonce::from_slice(&NOTE_NONCE),
+			cipher,
+			out_body.expose_mut(),
+			Tag::from_slice(&out_cipher[64..]),
+		)
+		.map_err(|_| EciesError::AuthenticationFailure)?;
+
+	let mut sk_bytes = SecretBytes::new([0u8; 32]);
+	sk_bytes
+		.expose_mut()
+		.copy_from_slice(&out_body.expose()[0..32]);
+	let ephemeral_sk = StaticSecret::from(*sk_bytes.expose());
+	if PublicKey::from(&ephemeral_sk).as_bytes() != parsed.ephemeral_pk.as_bytes() {
+		return Err(EciesError::MalformedPlaintext);
+	}
+	let mut pk = [0u8; 32];
+	pk.copy_from_slice(&out_body.expose()[32..64]);
+
+	let shared_secret = ephemeral_sk.diffie_hellman(&PublicKey::from(pk));
+	open_note(&parsed, shared_secret.as_bytes(), &pk)
+}
+
+/// The outgoing key is bound to the note ciphertext through its digest,
+/// which takes the place of the recipient key in the KDF transcript.
+fn outgoing_key(ovk: &SecretBytes, ephemeral_pk: &[u8; 32], cipher: &[u8]) -> SecretBytes {
+	let mut digest = [0u8; 32];
+	digest.copy_from_slice(Blake2s::digest(cipher).as_slice());
+	MantaKdf::new(OUTGOING_KDF_CONTEXT, ovk.expose(), ephemeral_pk, &digest)
+		.derive(MANTA_KDF_ENCRYPTION_LABEL)
+}
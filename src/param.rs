@@ -14,6 +14,12 @@
 // You should have received a copy of the GNU General Public License
 // along with manta-crypto.  If not, see <http://www.gnu.org/licenses/>.
 
+use crate::{
+	merkle_tree::{TreeHashing, TreeVersion},
+	poseidon::{PoseidonCRH, PoseidonCRHGadget},
+	CheckpointedMerkleTree, IncrementalMerkleTree, MembershipPath, MembershipPathVar,
+	SparseMerkleProof, SparseMerkleProofVar, SparseMerkleTree, StoredMerkleTree, WitnessTracker,
+};
 use ark_bls12_381::Bls12_381;
 use ark_crypto_primitives::{
 	commitment::pedersen::{constraints::CommGadget, Commitment, Window},
@@ -34,6 +40,8 @@ pub const ZK_PPARAM_SEED: [u8; 32] = [3u8; 32];
 pub const HASH_PARAM_SEED: [u8; 32] = [1u8; 32];
 /// The seed that is used to generate commitment parameters
 pub const COMMIT_PARAM_SEED: [u8; 32] = [2u8; 32];
+/// The seed that is used to generate Poseidon hash parameters
+pub const POSEIDON_PARAM_SEED: [u8; 32] = [4u8; 32];
 
 //=======================
 // pedersen hash and related definitions
@@ -42,8 +50,8 @@ pub const COMMIT_PARAM_SEED: [u8; 32] = [2u8; 32];
 pub(crate) const PERDERSON_WINDOW_SIZE: usize = 4;
 pub(crate) const PERDERSON_WINDOW_NUM: usize = 256;
 
-/// The depth of the merkle tree.
-const TREE_DEPTH: usize = 21;
+/// The depth of the ledger merkle tree, i.e., its `HEIGHT`.
+pub const TREE_DEPTH: usize = 21;
 
 #[derive(Clone)]
 pub struct PedersenWindow;
@@ -59,23 +67,79 @@ pub type HashParam = <Hash as FixedLengthCRH>::Parameters;
 //=======================
 // Merkle tree for the ledger, using Perdersen hash
 //=======================
-/// Manta's parameters for the Merkle tree.
-#[derive(Debug, Clone, Copy)]
-pub struct MerkleTreeParams;
-impl Config for MerkleTreeParams {
-	const HEIGHT: usize = TREE_DEPTH;
+/// Manta's parameters for a Merkle tree of `DEPTH` levels, which holds
+/// `2^(DEPTH - 1)` leaves, e.g., a smaller tree for a test network.
+/// `DEPTH` is at least 2.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MerkleTreeConfig<const DEPTH: usize>;
+impl<const DEPTH: usize> Config for MerkleTreeConfig<DEPTH> {
+	const HEIGHT: usize = DEPTH;
+	type H = Hash;
+}
+impl<const DEPTH: usize> TreeHashing for MerkleTreeConfig<DEPTH> {
+	const HEIGHT: usize = DEPTH;
+	type H = Hash;
+	const VERSION: TreeVersion = TreeVersion::Legacy;
+}
+/// Manta's parameters for the ledger Merkle tree.
+pub type MerkleTreeParams = MerkleTreeConfig<TREE_DEPTH>;
+
+/// Manta's parameters for a Merkle tree of `DEPTH` levels, whose leaves and
+/// inner nodes are hashed with distinct tags. Its roots are not the ones of
+/// `ark_crypto_primitives::MerkleTree`, so that it does not implement its
+/// `Config`, and only instantiates the trees and the paths of this crate.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TaggedMerkleTreeConfig<const DEPTH: usize>;
+impl<const DEPTH: usize> TreeHashing for TaggedMerkleTreeConfig<DEPTH> {
+	const HEIGHT: usize = DEPTH;
 	type H = Hash;
+	const VERSION: TreeVersion = TreeVersion::Tagged;
 }
+/// Manta's parameters for the tagged ledger Merkle tree.
+pub type TaggedMerkleTreeParams = TaggedMerkleTreeConfig<TREE_DEPTH>;
 
 /// A merkle tree that is instantiated with Manta parameters.
 pub type LedgerMerkleTree = MerkleTree<MerkleTreeParams>;
 /// The root of the tree.
 pub type LedgerMerkleTreeRoot = Digest<MerkleTreeParams>;
+/// An append-only frontier of the ledger tree, with identical roots.
+pub type LedgerIncrementalMerkleTree = IncrementalMerkleTree<MerkleTreeParams>;
+/// The ledger tree of a node, with its recent roots and checkpoints.
+pub type LedgerCheckpointedMerkleTree = CheckpointedMerkleTree<MerkleTreeParams>;
+/// A frontier of the ledger tree that keeps the paths of a wallet's own leaves.
+pub type LedgerWitnessTracker = WitnessTracker<MerkleTreeParams>;
+/// The ledger tree over a storage of its nodes, with identical roots.
+pub type LedgerStoredMerkleTree<S> = StoredMerkleTree<MerkleTreeParams, S>;
 
 /// The membership is a path on the merkle tree, including the leaf itself.
 /// It can be used to verify that a leaf is indeed on a tree.
 #[allow(dead_code)]
 pub type AccountMembership = Path<MerkleTreeParams>;
+/// A serializable membership path on the ledger tree, that fixes the index of the leaf.
+pub type LedgerMembershipPath = MembershipPath<MerkleTreeParams>;
+
+/// A merkle tree of `DEPTH` levels that is instantiated with Manta parameters.
+pub type LedgerMerkleTreeOfDepth<const DEPTH: usize> = MerkleTree<MerkleTreeConfig<DEPTH>>;
+/// An append-only frontier of a tree of `DEPTH` levels.
+pub type LedgerIncrementalMerkleTreeOfDepth<const DEPTH: usize> =
+	IncrementalMerkleTree<MerkleTreeConfig<DEPTH>>;
+/// A membership path on a tree of `DEPTH` levels.
+pub type LedgerMembershipPathOfDepth<const DEPTH: usize> = MembershipPath<MerkleTreeConfig<DEPTH>>;
+
+/// The ledger tree with domain-separated hashing, as an append-only frontier.
+pub type TaggedLedgerMerkleTree = IncrementalMerkleTree<TaggedMerkleTreeParams>;
+/// A membership path on the tagged ledger tree.
+pub type TaggedLedgerMembershipPath = MembershipPath<TaggedMerkleTreeParams>;
+
+//=======================
+// Sparse merkle tree for the void numbers, using Perdersen hash
+//=======================
+/// The set of void numbers, as a sparse merkle tree.
+pub type VoidNumberTree = SparseMerkleTree<Hash>;
+/// A proof of the membership or non-membership of a void number.
+pub type VoidNumberProof = SparseMerkleProof<Hash>;
+/// The gadget of a `VoidNumberProof`.
+pub type VoidNumberProofVar = SparseMerkleProofVar<Hash, HashVar>;
 
 //=======================
 // Commitments
@@ -95,8 +159,60 @@ pub type HashOutputVar = <HashVar as FixedLengthCRHGadget<Hash, Fq>>::OutputVar;
 pub type HashParamVar = <HashVar as FixedLengthCRHGadget<Hash, Fq>>::ParametersVar;
 
 // gadget for private coin account membership
+// its witness is an ark `Path`, whose fields are private to ark, so that a
+// `LedgerMembershipPath` cannot be turned into one: the circuits that prove
+// membership from a `LedgerMembershipPath` switch to `LedgerMembershipPathVar`
 #[allow(dead_code)]
 pub type AccountMembershipVar = PathVar<MerkleTreeParams, HashVar, Fq>;
+/// The gadget of a `LedgerMembershipPath`.
+pub type LedgerMembershipPathVar = MembershipPathVar<MerkleTreeParams, HashVar>;
+/// The gadget of a `LedgerMembershipPathOfDepth`.
+pub type LedgerMembershipPathVarOfDepth<const DEPTH: usize> =
+	MembershipPathVar<MerkleTreeConfig<DEPTH>, HashVar>;
+/// The gadget of a `TaggedLedgerMembershipPath`.
+pub type TaggedLedgerMembershipPathVar = MembershipPathVar<TaggedMerkleTreeParams, HashVar>;
+
+//=======================
+// Poseidon hash and related definitions
+// the hash function is defined over the BLS12-381 scalar field
+//=======================
+pub type PoseidonHash = PoseidonCRH;
+pub type PoseidonHashOutput = <PoseidonHash as FixedLengthCRH>::Output;
+pub type PoseidonHashParam = <PoseidonHash as FixedLengthCRH>::Parameters;
+
+/// Parameters for a Merkle tree of `DEPTH` levels that uses Poseidon instead of Perdersen hash.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PoseidonMerkleTreeConfig<const DEPTH: usize>;
+impl<const DEPTH: usize> Config for PoseidonMerkleTreeConfig<DEPTH> {
+	const HEIGHT: usize = DEPTH;
+	type H = PoseidonHash;
+}
+impl<const DEPTH: usize> TreeHashing for PoseidonMerkleTreeConfig<DEPTH> {
+	const HEIGHT: usize = DEPTH;
+	type H = PoseidonHash;
+	const VERSION: TreeVersion = TreeVersion::Legacy;
+}
+/// Parameters for the ledger Merkle tree that uses Poseidon instead of Perdersen hash.
+pub type PoseidonMerkleTreeParams = PoseidonMerkleTreeConfig<TREE_DEPTH>;
+
+/// A Poseidon merkle tree that is instantiated with Manta parameters.
+pub type PoseidonLedgerMerkleTree = MerkleTree<PoseidonMerkleTreeParams>;
+/// The root of the Poseidon tree.
+pub type PoseidonLedgerMerkleTreeRoot = Digest<PoseidonMerkleTreeParams>;
+/// The membership of a leaf on the Poseidon tree.
+#[allow(dead_code)]
+pub type PoseidonAccountMembership = Path<PoseidonMerkleTreeParams>;
+
+// gadgets for Poseidon hash function
+pub type PoseidonHashVar = PoseidonCRHGadget;
+pub type PoseidonHashOutputVar =
+	<PoseidonHashVar as FixedLengthCRHGadget<PoseidonHash, Fq>>::OutputVar;
+pub type PoseidonHashParamVar =
+	<PoseidonHashVar as FixedLengthCRHGadget<PoseidonHash, Fq>>::ParametersVar;
+
+// gadget for private coin account membership on the Poseidon tree
+#[allow(dead_code)]
+pub type PoseidonAccountMembershipVar = PathVar<PoseidonMerkleTreeParams, PoseidonHashVar, Fq>;
 
 //=======================
 // ZK proofs over BLS curve
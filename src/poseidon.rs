@@ -0,0 +1,250 @@
+// Copyright 2019-2021 Manta Network.
+// This file is part of manta-crypto.
+//
+// manta-crypto is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// manta-crypto is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with manta-crypto.  If not, see <http://www.gnu.org/licenses/>.
+
+//! This file implements the Poseidon hash function over the BLS12-381 scalar
+//! field (i.e., the base field `Fq` of the JubJub curve), together with its
+//! R1CS gadget. Both implement arkworks' `FixedLengthCRH` interfaces, so that
+//! Poseidon can be used as the hash function of a merkle tree `Config`.
+use ark_crypto_primitives::{
+	crh::{FixedLengthCRH, FixedLengthCRHGadget},
+	Error,
+};
+use ark_ed_on_bls12_381::Fq;
+use ark_ff::{Field, PrimeField, Zero};
+use ark_r1cs_std::{fields::fp::FpVar, prelude::*};
+use ark_relations::r1cs::{Namespace, SynthesisError};
+use ark_std::{borrow::Borrow, rand::Rng, vec, vec::Vec, UniformRand};
+
+/// The width of the Poseidon state.
+pub(crate) const POSEIDON_WIDTH: usize = 3;
+/// The number of state elements that absorb the input.
+const POSEIDON_RATE: usize = 2;
+/// The number of full rounds; half of them before the partial rounds.
+const POSEIDON_FULL_ROUNDS: usize = 8;
+/// The number of partial rounds.
+const POSEIDON_PARTIAL_ROUNDS: usize = 57;
+/// The exponent of the S-box.
+const POSEIDON_ALPHA: u64 = 5;
+/// The number of bytes that are packed into a single field element.
+const POSEIDON_BYTES_PER_ELEMENT: usize = 31;
+/// The maximum number of input bytes, i.e., two serialized field elements.
+pub(crate) const POSEIDON_INPUT_BYTES: usize = 64;
+
+/// The parameters of the Poseidon permutation.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PoseidonParameters {
+	/// the round constants, one row per round
+	pub round_constants: Vec<[Fq; POSEIDON_WIDTH]>,
+	/// the maximum distance separable matrix
+	pub mds: [[Fq; POSEIDON_WIDTH]; POSEIDON_WIDTH],
+}
+
+/// Poseidon hash function with a width 3 state and x^5 S-boxes.
+pub struct PoseidonCRH;
+
+impl FixedLengthCRH for PoseidonCRH {
+	const INPUT_SIZE_BITS: usize = POSEIDON_INPUT_BYTES * 8;
+	type Output = Fq;
+	type Parameters = PoseidonParameters;
+
+	/// Sample the round constants from `rng`.
+	/// The MDS matrix is the Cauchy matrix `1/(x_i + y_j)` with
+	/// `x_i = i` and `y_j = POSEIDON_WIDTH + j`.
+	fn setup<R: Rng>(rng: &mut R) -> Result<Self::Parameters, Error> {
+		let round_constants = (0..POSEIDON_FULL_ROUNDS + POSEIDON_PARTIAL_ROUNDS)
+			.map(|_| {
+				let mut row = [Fq::zero(); POSEIDON_WIDTH];
+				for e in row.iter_mut() {
+					*e = Fq::rand(rng);
+				}
+				row
+			})
+			.collect();
+
+		let mut mds = [[Fq::zero(); POSEIDON_WIDTH]; POSEIDON_WIDTH];
+		for (i, row) in mds.iter_mut().enumerate() {
+			for (j, e) in row.iter_mut().enumerate() {
+				*e = Fq::from((i + POSEIDON_WIDTH + j) as u64)
+					.inverse()
+					.expect("Cauchy matrix entries are non-zero");
+			}
+		}
+
+		Ok(PoseidonParameters {
+			round_constants,
+			mds,
+		})
+	}
+
+	/// Hash up to `POSEIDON_INPUT_BYTES` bytes.
+	/// The input is zero padded and packed into field elements,
+	/// `POSEIDON_BYTES_PER_ELEMENT` little-endian bytes at a time.
+	fn evaluate(parameters: &Self::Parameters, input: &[u8]) -> Result<Self::Output, Error> {
+		if input.len() * 8 > Self::INPUT_SIZE_BITS {
+			panic!("incorrect input length {:?} for poseidon", input.len());
+		}
+		let mut padded_input = input.to_vec();
+		padded_input.resize(POSEIDON_INPUT_BYTES, 0u8);
+
+		let elements: Vec<Fq> = padded_input
+			.chunks(POSEIDON_BYTES_PER_ELEMENT)
+			.map(Fq::from_le_bytes_mod_order)
+			.collect();
+		Ok(hash_elements(parameters, &elements))
+	}
+}
+
+/// Sponge over the Poseidon permutation.
+/// The capacity element is initialized with the number of inputs.
+pub(crate) fn hash_elements(parameters: &PoseidonParameters, elements: &[Fq]) -> Fq {
+	let mut state = [Fq::zero(); POSEIDON_WIDTH];
+	state[0] = Fq::from(elements.len() as u64);
+	for chunk in elements.chunks(POSEIDON_RATE) {
+		for (s, e) in state[1..].iter_mut().zip(chunk) {
+			*s += e;
+		}
+		permute(parameters, &mut state);
+	}
+	state[1]
+}
+
+fn permute(parameters: &PoseidonParameters, state: &mut [Fq; POSEIDON_WIDTH]) {
+	for (round, constants) in parameters.round_constants.iter().enumerate() {
+		for (s, c) in state.iter_mut().zip(constants.iter()) {
+			*s += c;
+		}
+		if is_full_round(round) {
+			for s in state.iter_mut() {
+				*s = s.pow([POSEIDON_ALPHA]);
+			}
+		} else {
+			state[0] = state[0].pow([POSEIDON_ALPHA]);
+		}
+
+		let mut new_state = [Fq::zero(); POSEIDON_WIDTH];
+		for (new, row) in new_state.iter_mut().zip(parameters.mds.iter()) {
+			for (s, m) in state.iter().zip(row.iter()) {
+				*new += *s * m;
+			}
+		}
+		*state = new_state;
+	}
+}
+
+fn is_full_round(round: usize) -> bool {
+	let half = POSEIDON_FULL_ROUNDS / 2;
+	round < half || round >= half + POSEIDON_PARTIAL_ROUNDS
+}
+
+//=======================
+// gadgets
+//=======================
+
+/// The parameters of the Poseidon gadget.
+/// Parameters are public constants of the circuit,
+/// so they are never allocated as variables.
+#[derive(Clone)]
+pub struct PoseidonParametersVar {
+	pub parameters: PoseidonParameters,
+}
+
+impl AllocVar<PoseidonParameters, Fq> for PoseidonParametersVar {
+	fn new_variable<T: Borrow<PoseidonParameters>>(
+		_cs: impl Into<Namespace<Fq>>,
+		f: impl FnOnce() -> Result<T, SynthesisError>,
+		_mode: AllocationMode,
+	) -> Result<Self, SynthesisError> {
+		let parameters = f()?.borrow().clone();
+		Ok(Self { parameters })
+	}
+}
+
+/// Poseidon hash gadget.
+pub struct PoseidonCRHGadget;
+
+impl FixedLengthCRHGadget<PoseidonCRH, Fq> for PoseidonCRHGadget {
+	type OutputVar = FpVar<Fq>;
+	type ParametersVar = PoseidonParametersVar;
+
+	fn evaluate(
+		parameters: &Self::ParametersVar,
+		input: &[UInt8<Fq>],
+	) -> Result<Self::OutputVar, SynthesisError> {
+		if input.len() * 8 > PoseidonCRH::INPUT_SIZE_BITS {
+			panic!("incorrect input length {:?} for poseidon", input.len());
+		}
+		let mut padded_input = input.to_vec();
+		padded_input.resize(POSEIDON_INPUT_BYTES, UInt8::constant(0u8));
+
+		let elements = padded_input
+			.chunks(POSEIDON_BYTES_PER_ELEMENT)
+			.map(|chunk| Boolean::le_bits_to_fp_var(&chunk.to_bits_le()?))
+			.collect::<Result<Vec<_>, _>>()?;
+		hash_elements_var(&parameters.parameters, &elements)
+	}
+}
+
+/// Gadget for `hash_elements`.
+pub(crate) fn hash_elements_var(
+	parameters: &PoseidonParameters,
+	elements: &[FpVar<Fq>],
+) -> Result<FpVar<Fq>, SynthesisError> {
+	let mut state = vec![FpVar::<Fq>::zero(); POSEIDON_WIDTH];
+	state[0] = FpVar::constant(Fq::from(elements.len() as u64));
+	for chunk in elements.chunks(POSEIDON_RATE) {
+		for (s, e) in state[1..].iter_mut().zip(chunk) {
+			*s += e;
+		}
+		permute_var(parameters, &mut state)?;
+	}
+	Ok(state[1].clone())
+}
+
+fn permute_var(
+	parameters: &PoseidonParameters,
+	state: &mut Vec<FpVar<Fq>>,
+) -> Result<(), SynthesisError> {
+	for (round, constants) in parameters.round_constants.iter().enumerate() {
+		for (s, c) in state.iter_mut().zip(constants.iter()) {
+			*s += *c;
+		}
+		if is_full_round(round) {
+			for s in state.iter_mut() {
+				*s = sbox_var(s)?;
+			}
+		} else {
+			state[0] = sbox_var(&state[0])?;
+		}
+
+		*state = parameters
+			.mds
+			.iter()
+			.map(|row| {
+				state
+					.iter()
+					.zip(row.iter())
+					.fold(FpVar::<Fq>::zero(), |acc, (s, m)| acc + s * *m)
+			})
+			.collect();
+	}
+	Ok(())
+}
+
+fn sbox_var(x: &FpVar<Fq>) -> Result<FpVar<Fq>, SynthesisError> {
+	let x2 = x.square()?;
+	let x4 = x2.square()?;
+	Ok(x4 * x)
+}
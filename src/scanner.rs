@@ -0,0 +1,53 @@
+// Copyright 2019-2021 Manta Network.
+// This file is part of manta-crypto.
+//
+// manta-crypto is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// manta-crypto is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with manta-crypto.  If not, see <http://www.gnu.org/licenses/>.
+
+//! This file implements trial decryption of the note ciphertexts on chain.
+//! Ciphertexts whose view tag does not match are skipped without
+//! running AES-GCM.
+use crate::{note_encryption::decrypt_note, NotePlaintext, SecretBytes};
+use ark_std::vec::Vec;
+#[cfg(feature = "std")]
+use rayon::prelude::*;
+
+/// Scan `ciphertexts` with the viewing key `sk`, and return the index
+/// and the plaintext of every note that is encrypted to `sk`.
+pub fn scan_notes<'a, I>(sk: &SecretBytes, ciphertexts: I) -> Vec<(usize, NotePlaintext)>
+where
+	I: IntoIterator<Item = &'a [u8]>,
+{
+	ciphertexts
+		.into_iter()
+		.enumerate()
+		.filter_map(|(index, cipher)| decrypt_note(sk, cipher).ok().map(|note| (index, note)))
+		.collect()
+}
+
+/// Scan `ciphertexts` across threads, see `scan_notes`.
+#[cfg(feature = "std")]
+pub fn par_scan_notes<C>(sk: &SecretBytes, ciphertexts: &[C]) -> Vec<(usize, NotePlaintext)>
+where
+	C: AsRef<[u8]> + Sync,
+{
+	ciphertexts
+		.par_iter()
+		.enumerate()
+		.filter_map(|(index, cipher)| {
+			decrypt_note(sk, cipher.as_ref())
+				.ok()
+				.map(|note| (index, note))
+		})
+		.collect()
+}
@@ -0,0 +1,74 @@
+// Copyright 2019-2021 Manta Network.
+// This file is part of manta-crypto.
+//
+// manta-crypto is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// manta-crypto is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with manta-crypto.  If not, see <http://www.gnu.org/licenses/>.
+
+//! This file implements a wrapper for secret material.
+//! A secret is wiped from memory when it is dropped, and never shows up
+//! in `Debug` output. Its value is only reachable through `expose`,
+//! which makes every use of the secret explicit.
+use core::fmt;
+use subtle::ConstantTimeEq;
+use zeroize::Zeroize;
+
+/// A secret value that is zeroized on drop.
+pub struct Secret<T: Zeroize>(T);
+
+/// A 32 bytes secret, e.g., a private key or commitment randomness.
+pub type SecretBytes = Secret<[u8; 32]>;
+
+impl<T: Zeroize> Secret<T> {
+	/// Wrap a secret value.
+	pub fn new(secret: T) -> Self {
+		Self(secret)
+	}
+
+	/// Expose the secret value.
+	pub fn expose(&self) -> &T {
+		&self.0
+	}
+
+	/// Expose the secret value for writing, e.g., to fill it in place
+	/// without leaving a copy on the stack.
+	pub(crate) fn expose_mut(&mut self) -> &mut T {
+		&mut self.0
+	}
+}
+
+impl<T: Zeroize + Clone> Clone for Secret<T> {
+	fn clone(&self) -> Self {
+		Self(self.0.clone())
+	}
+}
+
+impl<T: Zeroize> Drop for Secret<T> {
+	fn drop(&mut self) {
+		self.0.zeroize();
+	}
+}
+
+impl<T: Zeroize> fmt::Debug for Secret<T> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str("Secret([REDACTED])")
+	}
+}
+
+/// Secrets are compared in constant time.
+impl PartialEq for SecretBytes {
+	fn eq(&self, other: &Self) -> bool {
+		self.0[..].ct_eq(&other.0[..]).into()
+	}
+}
+
+impl Eq for SecretBytes {}
@@ -0,0 +1,342 @@
+// Copyright 2019-2021 Manta Network.
+// This file is part of manta-crypto.
+//
+// manta-crypto is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// manta-crypto is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with manta-crypto.  If not, see <http://www.gnu.org/licenses/>.
+
+//! This file implements a sparse merkle tree over the set of void numbers,
+//! together with its gadget. The tree has a leaf for every 256-bit key,
+//! and the key of a void number is the void number itself:
+//! ```text
+//! leaf(key)  = H(LEAF_TAG | key)  if key is in the set
+//!              H(0)               otherwise
+//! node_{l+1} = H(node_l, sibling_l)  if the bit l of key is 0
+//!              H(sibling_l, node_l)  otherwise
+//! ```
+//! where the bits of the key are little-endian, from the leaf up.
+//! The same path proves membership from the set leaf, and non-membership
+//! from the empty leaf.
+//!
+//! Only the non-empty nodes are stored; an empty subtree of height `l`
+//! has the precomputed root `empty[l]`.
+use crate::{
+	membership::{hash_inner_node_gadget, hash_padded_gadget},
+	merkle_tree::{hash_empty, hash_inner_node, hash_padded, MerkleTreeError},
+	serdes::MantaSerDes,
+};
+use ark_crypto_primitives::{FixedLengthCRH, FixedLengthCRHGadget};
+use ark_ed_on_bls12_381::Fq;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{Namespace, SynthesisError};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use ark_std::{
+	borrow::Borrow,
+	collections::BTreeMap,
+	io::{Read, Write},
+	marker::PhantomData,
+	vec,
+	vec::Vec,
+};
+use manta_error::MantaError;
+
+/// The depth of the sparse merkle tree, i.e., the number of bits of a key.
+pub const SPARSE_MERKLE_TREE_DEPTH: usize = 256;
+/// The domain separation tag of a set leaf, which tells it apart from the empty leaf.
+const SPARSE_LEAF_TAG: u8 = 1;
+
+/// A sparse merkle tree over a set of 32-byte keys.
+pub struct SparseMerkleTree<H: FixedLengthCRH> {
+	param: H::Parameters,
+	/// the non-empty nodes, by level and by the key of any of their leaves,
+	/// with the `level` lowest bits cleared
+	nodes: BTreeMap<(usize, [u8; 32]), H::Output>,
+	/// `empty[l]` is the root of an empty subtree of height `l`
+	empty: Vec<H::Output>,
+}
+
+/// A path of the sparse merkle tree, that proves either the membership or
+/// the non-membership of a key.
+pub struct SparseMerkleProof<H: FixedLengthCRH> {
+	/// the siblings of the nodes on the path, from the leaf up
+	pub siblings: Vec<H::Output>,
+}
+
+impl<H: FixedLengthCRH> Clone for SparseMerkleProof<H> {
+	fn clone(&self) -> Self {
+		Self {
+			siblings: self.siblings.clone(),
+		}
+	}
+}
+
+impl<H: FixedLengthCRH> SparseMerkleTree<H> {
+	/// Build an empty tree.
+	pub fn new(param: H::Parameters) -> Result<Self, MerkleTreeError> {
+		let mut empty = vec![hash_empty::<H>(&param)?];
+		for level in 0..SPARSE_MERKLE_TREE_DEPTH {
+			let node = hash_inner_node::<H>(&param, &empty[level], &empty[level])?;
+			empty.push(node);
+		}
+		Ok(Self {
+			param,
+			nodes: BTreeMap::new(),
+			empty,
+		})
+	}
+
+	/// The root of the tree.
+	pub fn root(&self) -> H::Output {
+		self.node(SPARSE_MERKLE_TREE_DEPTH, &[0u8; 32])
+	}
+
+	/// Whether the key is in the set.
+	pub fn contains(&self, key: &[u8; 32]) -> bool {
+		self.nodes.contains_key(&(0, *key))
+	}
+
+	/// Insert a key into the set, and return whether it was absent.
+	///
+	/// # <weight>
+	/// Steps:
+	///     1. set node = H(LEAF_TAG | key)
+	///     2. for level in 0..DEPTH:
+	///         a. store node at (level, key)
+	///         b. hash node with its sibling, in the order of the bit level of key
+	///     3. store the root
+	/// # </weight>
+	pub fn insert(&mut self, key: &[u8; 32]) -> Result<bool, MerkleTreeError> {
+		if self.contains(key) {
+			return Ok(false);
+		}
+		let mut node = leaf_hash::<H>(&self.param, key)?;
+		for level in 0..SPARSE_MERKLE_TREE_DEPTH {
+			self.nodes
+				.insert((level, node_key(key, level)), node.clone());
+			let sibling = self.node(level, &sibling_key(key, level));
+			node = if key_bit(key, level) {
+				hash_inner_node::<H>(&self.param, &sibling, &node)?
+			} else {
+				hash_inner_node::<H>(&self.param, &node, &sibling)?
+			};
+		}
+		self.nodes
+			.insert((SPARSE_MERKLE_TREE_DEPTH, [0u8; 32]), node);
+		Ok(true)
+	}
+
+	/// The path of a key, that proves its membership if the key is in
+	/// the set, and its non-membership otherwise.
+	pub fn prove(&self, key: &[u8; 32]) -> SparseMerkleProof<H> {
+		SparseMerkleProof {
+			siblings: (0..SPARSE_MERKLE_TREE_DEPTH)
+				.map(|level| self.node(level, &sibling_key(key, level)))
+				.collect(),
+		}
+	}
+
+	/// The node at `level` above the leaf of `key`.
+	fn node(&self, level: usize, key: &[u8; 32]) -> H::Output {
+		self.nodes
+			.get(&(level, *key))
+			.cloned()
+			.unwrap_or_else(|| self.empty[level].clone())
+	}
+}
+
+impl<H: FixedLengthCRH> SparseMerkleProof<H> {
+	/// The root of the tree, if the path ends at the leaf of `key`,
+	/// which is set iff `is_member`.
+	pub fn root(
+		&self,
+		param: &H::Parameters,
+		key: &[u8; 32],
+		is_member: bool,
+	) -> Result<H::Output, MerkleTreeError> {
+		if self.siblings.len() != SPARSE_MERKLE_TREE_DEPTH {
+			return Err(MerkleTreeError::MalformedProof);
+		}
+		let mut node = if is_member {
+			leaf_hash::<H>(param, key)?
+		} else {
+			hash_empty::<H>(param)?
+		};
+		for (level, sibling) in self.siblings.iter().enumerate() {
+			node = if key_bit(key, level) {
+				hash_inner_node::<H>(param, sibling, &node)?
+			} else {
+				hash_inner_node::<H>(param, &node, sibling)?
+			};
+		}
+		Ok(node)
+	}
+
+	/// Verify that `key` is in the set of the tree of `root`.
+	pub fn verify_membership(
+		&self,
+		param: &H::Parameters,
+		root: &H::Output,
+		key: &[u8; 32],
+	) -> Result<bool, MerkleTreeError> {
+		Ok(self.root(param, key, true)? == *root)
+	}
+
+	/// Verify that `key` is not in the set of the tree of `root`.
+	pub fn verify_non_membership(
+		&self,
+		param: &H::Parameters,
+		root: &H::Output,
+		key: &[u8; 32],
+	) -> Result<bool, MerkleTreeError> {
+		Ok(self.root(param, key, false)? == *root)
+	}
+}
+
+impl<H: FixedLengthCRH> MantaSerDes for SparseMerkleProof<H> {
+	/// Serialize the siblings of the proof.
+	fn serialize<W: Write>(&self, mut writer: W) -> Result<(), MantaError> {
+		self.siblings.serialize(&mut writer)?;
+		Ok(())
+	}
+
+	/// Deserialize a proof. The siblings are checked to be valid digests.
+	fn deserialize<R: Read>(mut reader: R) -> Result<Self, MantaError> {
+		let siblings = Vec::<H::Output>::deserialize(&mut reader)?;
+		Ok(Self { siblings })
+	}
+}
+
+/// The gadget of a `SparseMerkleProof`.
+pub struct SparseMerkleProofVar<H: FixedLengthCRH, HG: FixedLengthCRHGadget<H, Fq>> {
+	/// the siblings of the nodes on the path, from the leaf up
+	siblings: Vec<HG::OutputVar>,
+	_hash: PhantomData<H>,
+}
+
+impl<H: FixedLengthCRH, HG: FixedLengthCRHGadget<H, Fq>> AllocVar<SparseMerkleProof<H>, Fq>
+	for SparseMerkleProofVar<H, HG>
+{
+	fn new_variable<T: Borrow<SparseMerkleProof<H>>>(
+		cs: impl Into<Namespace<Fq>>,
+		f: impl FnOnce() -> Result<T, SynthesisError>,
+		mode: AllocationMode,
+	) -> Result<Self, SynthesisError> {
+		let ns = cs.into();
+		let cs = ns.cs();
+		// the proof is missing at setup, where only the shape matters
+		let proof = f();
+		let proof: Result<&SparseMerkleProof<H>, SynthesisError> = match &proof {
+			Ok(proof) => Ok(proof.borrow()),
+			Err(e) => Err(*e),
+		};
+		if let Ok(proof) = proof {
+			if proof.siblings.len() != SPARSE_MERKLE_TREE_DEPTH {
+				return Err(SynthesisError::Unsatisfiable);
+			}
+		}
+		let siblings = (0..SPARSE_MERKLE_TREE_DEPTH)
+			.map(|level| {
+				HG::OutputVar::new_variable(
+					cs.clone(),
+					|| proof.map(|proof| proof.siblings[level].clone()),
+					mode,
+				)
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+		Ok(Self {
+			siblings,
+			_hash: PhantomData,
+		})
+	}
+}
+
+impl<H: FixedLengthCRH, HG: FixedLengthCRHGadget<H, Fq>> SparseMerkleProofVar<H, HG> {
+	/// Gadget for `SparseMerkleProof::root`, from the bytes of the key.
+	pub fn root(
+		&self,
+		param: &HG::ParametersVar,
+		key: &[UInt8<Fq>],
+		is_member: &Boolean<Fq>,
+	) -> Result<HG::OutputVar, SynthesisError> {
+		if key.len() != 32 {
+			return Err(SynthesisError::Unsatisfiable);
+		}
+		let mut leaf = vec![UInt8::constant(SPARSE_LEAF_TAG)];
+		leaf.extend_from_slice(key);
+		// a constant `is_member` only needs the hash of its own leaf
+		let mut node = match is_member {
+			Boolean::Constant(true) => hash_padded_gadget::<H, HG>(param, leaf)?,
+			Boolean::Constant(false) => hash_padded_gadget::<H, HG>(param, Vec::new())?,
+			_ => HG::OutputVar::conditionally_select(
+				is_member,
+				&hash_padded_gadget::<H, HG>(param, leaf)?,
+				&hash_padded_gadget::<H, HG>(param, Vec::new())?,
+			)?,
+		};
+		for (bit, sibling) in key.to_bits_le()?.iter().zip(self.siblings.iter()) {
+			let left = HG::OutputVar::conditionally_select(bit, sibling, &node)?;
+			let right = HG::OutputVar::conditionally_select(bit, &node, sibling)?;
+			node = hash_inner_node_gadget::<H, HG>(param, &left, &right)?;
+		}
+		Ok(node)
+	}
+
+	/// Gadget for `SparseMerkleProof::verify_membership`.
+	pub fn check_membership(
+		&self,
+		param: &HG::ParametersVar,
+		root: &HG::OutputVar,
+		key: &[UInt8<Fq>],
+	) -> Result<Boolean<Fq>, SynthesisError> {
+		self.root(param, key, &Boolean::TRUE)?.is_eq(root)
+	}
+
+	/// Gadget for `SparseMerkleProof::verify_non_membership`.
+	pub fn check_non_membership(
+		&self,
+		param: &HG::ParametersVar,
+		root: &HG::OutputVar,
+		key: &[UInt8<Fq>],
+	) -> Result<Boolean<Fq>, SynthesisError> {
+		self.root(param, key, &Boolean::FALSE)?.is_eq(root)
+	}
+}
+
+/// `H(LEAF_TAG | key)`
+fn leaf_hash<H: FixedLengthCRH>(
+	param: &H::Parameters,
+	key: &[u8; 32],
+) -> Result<H::Output, MerkleTreeError> {
+	hash_padded::<H>(param, [[SPARSE_LEAF_TAG].as_ref(), key.as_ref()].concat())
+}
+
+/// The bit `level` of the key, i.e., whether the node at `level` is a right child.
+fn key_bit(key: &[u8; 32], level: usize) -> bool {
+	(key[level / 8] >> (level % 8)) & 1 == 1
+}
+
+/// The key of the node at `level`, i.e., the key with its `level` lowest bits cleared.
+fn node_key(key: &[u8; 32], level: usize) -> [u8; 32] {
+	let mut res = *key;
+	for (i, byte) in res.iter_mut().enumerate() {
+		let cleared = level.saturating_sub(8 * i).min(8);
+		*byte &= (0xffu16 << cleared) as u8;
+	}
+	res
+}
+
+/// The key of the sibling of the node at `level`.
+fn sibling_key(key: &[u8; 32], level: usize) -> [u8; 32] {
+	let mut res = node_key(key, level);
+	res[level / 8] ^= 1 << (level % 8);
+	res
+}
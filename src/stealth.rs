@@ -0,0 +1,216 @@
+// Copyright 2019-2021 Manta Network.
+// This file is part of manta-crypto.
+//
+// manta-crypto is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// manta-crypto is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with manta-crypto.  If not, see <http://www.gnu.org/licenses/>.
+
+//! This file implements one-time (stealth) addresses over the JubJub curve.
+//! A receiver publishes a view key `A = a * G` and a spend key `B = b * G`.
+//! For every payment, the sender samples `r` and derives
+//! ```text
+//! R = r * G
+//! P = H(r * A, R) * G + B
+//! ```
+//! and uses `P` as the commitment public key of the payment, next to `R`.
+//! Since `r * A = a * R`, the viewing key `(a, B)` recognizes `P`,
+//! and only the spending key `(a, b)` knows its secret `H(a * R, R) + b`.
+//! Two payments to the same receiver share no key.
+use crate::SecretBytes;
+use ark_ec::{AffineCurve, ProjectiveCurve};
+use ark_ed_on_bls12_381::{EdwardsAffine, EdwardsProjective, Fr};
+use ark_ff::PrimeField;
+use ark_serialize::CanonicalSerialize;
+use ark_std::{
+	rand::{CryptoRng, RngCore},
+	UniformRand,
+};
+use blake2::{Blake2s, Digest};
+
+/// The domain separation tag of the one-time key derivation.
+const STEALTH_HASH_TAG: &[u8] = b"manta stealth address";
+
+/// The public keys that a receiver publishes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StealthAddress {
+	/// the view public key `A = a * G`
+	pub view_pk: EdwardsAffine,
+	/// the spend public key `B = b * G`
+	pub spend_pk: EdwardsAffine,
+}
+
+/// A key that recognizes the payments to a stealth address, but cannot spend them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StealthViewingKey {
+	/// the view secret key `a`, as a little-endian scalar
+	pub view_sk: SecretBytes,
+	/// the spend public key `B`
+	pub spend_pk: EdwardsAffine,
+}
+
+/// The secret keys of a stealth address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StealthSpendingKey {
+	/// the view secret key `a`, as a little-endian scalar
+	pub view_sk: SecretBytes,
+	/// the spend secret key `b`, as a little-endian scalar
+	pub spend_sk: SecretBytes,
+}
+
+/// The per-payment keys that a sender derives from a stealth address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OneTimeAddress {
+	/// the ephemeral public key `R = r * G`, published with the payment
+	pub ephemeral_pk: EdwardsAffine,
+	/// the one-time public key `P`
+	pub one_time_pk: EdwardsAffine,
+}
+
+impl StealthSpendingKey {
+	/// Sample a pair of secret keys.
+	pub fn new<R: RngCore + CryptoRng>(rng: &mut R) -> Self {
+		Self {
+			view_sk: scalar_to_secret(&Fr::rand(rng)),
+			spend_sk: scalar_to_secret(&Fr::rand(rng)),
+		}
+	}
+
+	/// The stealth address of the keys.
+	pub fn address(&self) -> StealthAddress {
+		StealthAddress {
+			view_pk: mul_generator(&secret_to_scalar(&self.view_sk)),
+			spend_pk: mul_generator(&secret_to_scalar(&self.spend_sk)),
+		}
+	}
+
+	/// The viewing key of the keys.
+	pub fn viewing_key(&self) -> StealthViewingKey {
+		StealthViewingKey {
+			view_sk: self.view_sk.clone(),
+			spend_pk: mul_generator(&secret_to_scalar(&self.spend_sk)),
+		}
+	}
+
+	/// The secret key `x` of a one-time address, with `P = x * G`,
+	/// or `None` if the address does not belong to the keys.
+	pub fn one_time_sk(&self, address: &OneTimeAddress) -> Option<SecretBytes> {
+		if !self.viewing_key().recognize(address) {
+			return None;
+		}
+		let shared_secret = address
+			.ephemeral_pk
+			.mul(secret_to_scalar(&self.view_sk).into_repr());
+		let sk = hash_to_scalar(&shared_secret.into_affine(), &address.ephemeral_pk)
+			+ secret_to_scalar(&self.spend_sk);
+		Some(scalar_to_secret(&sk))
+	}
+}
+
+impl StealthViewingKey {
+	/// Check whether a one-time address belongs to the stealth address.
+	///
+	/// # <weight>
+	/// Steps:
+	///     1. compute the shared secret ss = a * R
+	///     2. return P == H(ss, R) * G + B
+	/// # </weight>
+	pub fn recognize(&self, address: &OneTimeAddress) -> bool {
+		let shared_secret = address
+			.ephemeral_pk
+			.mul(secret_to_scalar(&self.view_sk).into_repr());
+		let expected = one_time_pk(
+			&shared_secret.into_affine(),
+			&address.ephemeral_pk,
+			&self.spend_pk,
+		);
+		expected == address.one_time_pk
+	}
+}
+
+impl StealthAddress {
+	/// Derive a fresh one-time address for a payment.
+	///
+	/// # <weight>
+	/// Steps:
+	///     1. sample a random, ephemeral scalar r
+	///     2. compute the ephemeral public key R = r * G
+	///     3. compute the shared secret ss = r * A
+	///     4. return (R, P = H(ss, R) * G + B)
+	/// # </weight>
+	pub fn derive_one_time<R: RngCore + CryptoRng>(&self, rng: &mut R) -> OneTimeAddress {
+		let ephemeral_sk = Fr::rand(rng);
+		let ephemeral_pk = mul_generator(&ephemeral_sk);
+		let shared_secret = self.view_pk.mul(ephemeral_sk.into_repr()).into_affine();
+		OneTimeAddress {
+			ephemeral_pk,
+			one_time_pk: one_time_pk(&shared_secret, &ephemeral_pk, &self.spend_pk),
+		}
+	}
+}
+
+impl OneTimeAddress {
+	/// The serialized one-time public key, that takes the place of the
+	/// commitment public key of the payment.
+	pub fn commitment_key(&self) -> [u8; 32] {
+		let mut res = [0u8; 32];
+		self.one_time_pk
+			.serialize(res.as_mut())
+			.expect("a curve point serializes into 32 bytes");
+		res
+	}
+}
+
+/// `P = H(ss, R) * G + B`
+fn one_time_pk(
+	shared_secret: &EdwardsAffine,
+	ephemeral_pk: &EdwardsAffine,
+	spend_pk: &EdwardsAffine,
+) -> EdwardsAffine {
+	let mut res = EdwardsProjective::prime_subgroup_generator()
+		.mul(hash_to_scalar(shared_secret, ephemeral_pk).into_repr());
+	res.add_assign_mixed(spend_pk);
+	res.into_affine()
+}
+
+/// `H(ss, R) = blake2s(tag | ss | R) mod r`
+fn hash_to_scalar(shared_secret: &EdwardsAffine, ephemeral_pk: &EdwardsAffine) -> Fr {
+	let mut buf = [0u8; 64];
+	shared_secret
+		.serialize(buf[0..32].as_mut())
+		.expect("a curve point serializes into 32 bytes");
+	ephemeral_pk
+		.serialize(buf[32..64].as_mut())
+		.expect("a curve point serializes into 32 bytes");
+
+	let mut hasher = Blake2s::new();
+	hasher.update(STEALTH_HASH_TAG);
+	hasher.update(&buf);
+	Fr::from_le_bytes_mod_order(hasher.finalize().as_slice())
+}
+
+fn mul_generator(scalar: &Fr) -> EdwardsAffine {
+	EdwardsProjective::prime_subgroup_generator()
+		.mul(scalar.into_repr())
+		.into_affine()
+}
+
+fn secret_to_scalar(secret: &SecretBytes) -> Fr {
+	Fr::from_le_bytes_mod_order(secret.expose())
+}
+
+fn scalar_to_secret(scalar: &Fr) -> SecretBytes {
+	let mut res = SecretBytes::new([0u8; 32]);
+	scalar
+		.serialize(res.expose_mut().as_mut())
+		.expect("a scalar serializes into 32 bytes");
+	res
+}
@@ -15,7 +15,13 @@
 // along with manta-crypto.  If not, see <http://www.gnu.org/licenses/>.
 
 use crate::*;
-use ark_crypto_primitives::{CommitmentScheme, FixedLengthCRH};
+use ark_crypto_primitives::{
+	commitment::pedersen::Randomness, CommitmentScheme, FixedLengthCRH, FixedLengthCRHGadget,
+};
+use ark_ec::ProjectiveCurve;
+use ark_ed_on_bls12_381::{Fq, Fr};
+use ark_r1cs_std::{alloc::AllocVar, bits::uint64::UInt64, uint8::UInt8, R1CSVar};
+use ark_relations::r1cs::ConstraintSystem;
 use ark_std::{rand::SeedableRng, vec::Vec};
 use rand_chacha::ChaCha20Rng;
 use x25519_dalek::{PublicKey, StaticSecret};
@@ -27,11 +33,232 @@ fn manta_dh() {
 	let receiver_sk = StaticSecret::new(rng.clone());
 	let receiver_pk = PublicKey::from(&receiver_sk);
 	let receiver_pk_bytes = receiver_pk.to_bytes();
-	let receiver_sk_bytes = receiver_sk.to_bytes();
+	let receiver_sk_bytes = SecretBytes::new(receiver_sk.to_bytes());
 	let value = 12345678;
-	let cipher: [u8; 48] = <MantaCrypto as Ecies>::encrypt(&receiver_pk_bytes, &value, &mut rng);
-	let rec_value = <MantaCrypto as Ecies>::decrypt(&receiver_sk_bytes, &cipher);
+	let cipher: [u8; 56] = <MantaCrypto as Ecies>::encrypt(&receiver_pk_bytes, &value, &mut rng);
+	let rec_value = <MantaCrypto as Ecies>::decrypt(&receiver_sk_bytes, &cipher).unwrap();
 	assert_eq!(value, rec_value);
+
+	// tampered ciphertexts are rejected
+	let mut tampered = cipher;
+	tampered[0] ^= 1;
+	assert_eq!(
+		<MantaCrypto as Ecies>::decrypt(&receiver_sk_bytes, &tampered),
+		Err(EciesError::AuthenticationFailure)
+	);
+
+	// the errors pass into a `MantaError`
+	let decrypt = |cipher: &[u8; 56]| -> Result<u64, manta_error::MantaError> {
+		let value = <MantaCrypto as Ecies>::decrypt(&receiver_sk_bytes, cipher)
+			.map_err(ark_crypto_primitives::Error::from)?;
+		Ok(value)
+	};
+	assert_eq!(decrypt(&cipher).unwrap(), value);
+	assert!(decrypt(&tampered).is_err());
+}
+
+#[test]
+fn manta_dh_legacy() {
+	// a ciphertext of the legacy 48 bytes format,
+	// encrypting 12345678 to the secret key [7u8; 32]
+	let cipher = [
+		93, 201, 197, 144, 99, 52, 33, 179, 3, 21, 142, 18, 95, 118, 235, 195, 49, 212, 171, 106,
+		206, 236, 150, 17, 55, 145, 112, 55, 147, 110, 96, 113, 111, 172, 87, 58, 254, 148, 217,
+		218, 132, 168, 2, 4, 72, 223, 193, 18,
+	];
+	assert_eq!(
+		decrypt_legacy(&SecretBytes::new([7u8; 32]), &cipher),
+		12345678
+	);
+}
+
+#[test]
+fn manta_kdf() {
+	let kdf = MantaKdf::new(b"context", &[1u8; 32], &[2u8; 32], &[3u8; 32]);
+	let encryption_key = kdf.derive(MANTA_KDF_ENCRYPTION_LABEL);
+	assert_ne!(encryption_key, kdf.derive(MANTA_KDF_TAG_LABEL));
+
+	// known answers, that pin the salt, the info encoding and the labels
+	assert_eq!(
+		*encryption_key.expose(),
+		[
+			180, 209, 194, 234, 214, 147, 31, 69, 219, 98, 197, 241, 215, 33, 96, 109, 26, 188, 65,
+			241, 124, 76, 162, 170, 16, 193, 134, 236, 147, 151, 129, 213,
+		]
+	);
+	assert_eq!(
+		*kdf.derive(MANTA_KDF_TAG_LABEL).expose(),
+		[
+			228, 120, 104, 223, 149, 114, 34, 215, 175, 108, 195, 104, 110, 92, 58, 97, 218, 98, 8,
+			138, 142, 217, 142, 18, 65, 220, 147, 72, 216, 185, 91, 135,
+		]
+	);
+
+	// the transcript is bound into every subkey
+	let other_pk = MantaKdf::new(b"context", &[1u8; 32], &[2u8; 32], &[4u8; 32]);
+	assert_ne!(encryption_key, other_pk.derive(MANTA_KDF_ENCRYPTION_LABEL));
+	let other_context = MantaKdf::new(b"other context", &[1u8; 32], &[2u8; 32], &[3u8; 32]);
+	assert_ne!(
+		encryption_key,
+		other_context.derive(MANTA_KDF_ENCRYPTION_LABEL)
+	);
+}
+
+#[test]
+fn manta_note_encryption() {
+	let mut rng = ChaCha20Rng::from_seed([1u8; 32]);
+	let (pk, sk) = <NoteEncryption as Ecies>::keygen(&mut rng);
+	let note = NotePlaintext {
+		value: 12345678,
+		asset_id: 2,
+		randomness: SecretBytes::new([3u8; 32]),
+		rho: [4u8; 32],
+		memo: [5u8; 300].to_vec(),
+	};
+	let cipher = <NoteEncryption as Ecies>::encrypt(&pk, &note, &mut rng);
+	assert_eq!(cipher[0], NOTE_CIPHERTEXT_VERSION);
+	assert_eq!(<NoteEncryption as Ecies>::decrypt(&sk, &cipher), Ok(note));
+
+	let mut tampered = cipher.clone();
+	tampered[40] ^= 1;
+	assert_eq!(
+		<NoteEncryption as Ecies>::decrypt(&sk, &tampered),
+		Err(EciesError::AuthenticationFailure)
+	);
+	let mut unknown_version = cipher;
+	unknown_version[0] = 0;
+	assert_eq!(
+		<NoteEncryption as Ecies>::decrypt(&sk, &unknown_version),
+		Err(EciesError::UnsupportedVersion(0))
+	);
+}
+
+#[test]
+fn manta_outgoing_note() {
+	let mut rng = ChaCha20Rng::from_seed([1u8; 32]);
+	let (pk, sk) = <NoteEncryption as Ecies>::keygen(&mut rng);
+	let ovk = SecretBytes::new([6u8; 32]);
+	let note = NotePlaintext {
+		value: 12345678,
+		asset_id: 1,
+		randomness: SecretBytes::new([3u8; 32]),
+		rho: [4u8; 32],
+		memo: b"thanks".to_vec(),
+	};
+
+	let (cipher, out_cipher) = encrypt_note_with_ovk(&pk, &ovk, &note, &mut rng);
+	assert_eq!(
+		<NoteEncryption as Ecies>::decrypt(&sk, &cipher),
+		Ok(note.clone())
+	);
+	assert_eq!(recover_outgoing_note(&ovk, &cipher, &out_cipher), Ok(note));
+	assert_eq!(
+		recover_outgoing_note(&SecretBytes::new([7u8; 32]), &cipher, &out_cipher),
+		Err(EciesError::AuthenticationFailure)
+	);
+}
+
+#[test]
+fn manta_multi_recipient_note() {
+	let mut rng = ChaCha20Rng::from_seed([1u8; 32]);
+	let (receiver_pk, receiver_sk) = <NoteEncryption as Ecies>::keygen(&mut rng);
+	let (auditor_pk, auditor_sk) = <NoteEncryption as Ecies>::keygen(&mut rng);
+	let (_, outsider_sk) = <NoteEncryption as Ecies>::keygen(&mut rng);
+	let note = NotePlaintext {
+		value: 12345678,
+		asset_id: 1,
+		randomness: SecretBytes::new([3u8; 32]),
+		rho: [4u8; 32],
+		memo: b"audited".to_vec(),
+	};
+
+	let cipher = encrypt_note_to_many(&[receiver_pk, auditor_pk], &note, &mut rng);
+	assert_eq!(cipher[0], MULTI_RECIPIENT_CIPHERTEXT_VERSION);
+	assert_eq!(
+		decrypt_multi_recipient_note(&receiver_sk, &cipher),
+		Ok(note.clone())
+	);
+	assert_eq!(
+		decrypt_multi_recipient_note(&auditor_sk, &cipher),
+		Ok(note.clone())
+	);
+	assert_eq!(
+		decrypt_multi_recipient_note(&outsider_sk, &cipher),
+		Err(EciesError::AuthenticationFailure)
+	);
+
+	// the recipient headers are authenticated with the body
+	let mut tampered = cipher.clone();
+	tampered[66 + 56 + 10] ^= 1;
+	assert_eq!(
+		decrypt_multi_recipient_note(&receiver_sk, &tampered),
+		Err(EciesError::AuthenticationFailure)
+	);
+	assert_eq!(
+		decrypt_multi_recipient_note(&receiver_sk, &cipher[..100]),
+		Err(EciesError::MalformedCiphertext)
+	);
+
+	// a sender that wraps a different content key to the auditor is caught
+	use crate::multi_recipient::{content_key_commitment, wrap_content_key};
+	use aes_gcm::{
+		aead::{AeadInPlace, NewAead},
+		Aes256Gcm, Key, Nonce,
+	};
+	let cek = SecretBytes::new([5u8; 32]);
+	let other_cek = SecretBytes::new([6u8; 32]);
+	let ephemeral_sk = StaticSecret::new(&mut rng);
+	let mut forged = ark_std::vec![MULTI_RECIPIENT_CIPHERTEXT_VERSION];
+	forged.extend_from_slice(PublicKey::from(&ephemeral_sk).as_bytes());
+	forged.push(2);
+	forged.extend_from_slice(&content_key_commitment(&cek));
+	let prefix = forged.clone();
+	forged.extend(wrap_content_key(&ephemeral_sk, &receiver_pk, &prefix, &cek));
+	forged.extend(wrap_content_key(
+		&ephemeral_sk,
+		&auditor_pk,
+		&prefix,
+		&other_cek,
+	));
+	let mut body = note.to_bytes();
+	let tag = Aes256Gcm::new(Key::from_slice(cek.expose()))
+		.encrypt_in_place_detached(Nonce::from_slice(&[0u8; 12]), &forged, &mut body)
+		.unwrap();
+	forged.extend_from_slice(&body);
+	forged.extend_from_slice(tag.as_slice());
+	assert_eq!(
+		decrypt_multi_recipient_note(&receiver_sk, &forged),
+		Ok(note.clone())
+	);
+	assert_eq!(
+		decrypt_multi_recipient_note(&auditor_sk, &forged),
+		Err(EciesError::AuthenticationFailure)
+	);
+}
+
+#[test]
+fn manta_scan_notes() {
+	let mut rng = ChaCha20Rng::from_seed([1u8; 32]);
+	let (pk, sk) = <NoteEncryption as Ecies>::keygen(&mut rng);
+	let (other_pk, _) = <NoteEncryption as Ecies>::keygen(&mut rng);
+	let note = |value| NotePlaintext {
+		value,
+		asset_id: 1,
+		randomness: SecretBytes::new([3u8; 32]),
+		rho: [4u8; 32],
+		memo: Vec::new(),
+	};
+
+	let ciphertexts = [
+		<NoteEncryption as Ecies>::encrypt(&other_pk, &note(1), &mut rng),
+		<NoteEncryption as Ecies>::encrypt(&pk, &note(2), &mut rng),
+		<NoteEncryption as Ecies>::encrypt(&other_pk, &note(3), &mut rng),
+		<NoteEncryption as Ecies>::encrypt(&pk, &note(4), &mut rng),
+	];
+	let found = scan_notes(&sk, ciphertexts.iter().map(|c| c.as_slice()));
+	assert_eq!(found, [(1, note(2)), (3, note(4))].to_vec());
+	#[cfg(feature = "std")]
+	assert_eq!(par_scan_notes(&sk, &ciphertexts), found);
 }
 
 #[test]
@@ -61,6 +288,1063 @@ fn test_param_serdes() {
 	);
 }
 
+#[test]
+fn test_poseidon() {
+	let mut rng = ChaCha20Rng::from_seed(POSEIDON_PARAM_SEED);
+	let param = PoseidonHash::setup(&mut rng).unwrap();
+
+	// the gadget agrees with the native hash
+	let input = [7u8; 40];
+	let digest = PoseidonHash::evaluate(&param, &input).unwrap();
+	let cs = ConstraintSystem::<Fq>::new_ref();
+	let param_var = PoseidonHashParamVar::new_constant(cs.clone(), &param).unwrap();
+	let input_var = UInt8::new_witness_vec(cs.clone(), &input).unwrap();
+	let digest_var = PoseidonHashVar::evaluate(&param_var, &input_var).unwrap();
+	assert_eq!(digest_var.value().unwrap(), digest);
+	assert!(cs.is_satisfied().unwrap());
+
+	// Poseidon works as the hash function of a merkle tree
+	let leaves = [[1u8; 32], [2u8; 32], [3u8; 32]];
+	let tree = PoseidonLedgerMerkleTree::new(param.clone(), &leaves).unwrap();
+	let path = tree.generate_proof(1, &leaves[1]).unwrap();
+	assert!(path.verify(&param, &tree.root(), &leaves[1]).unwrap());
+	assert!(!path.verify(&param, &tree.root(), &leaves[0]).unwrap());
+}
+
+#[test]
+fn test_var_length_hash() {
+	let mut rng = ChaCha20Rng::from_seed(HASH_PARAM_SEED);
+	let param = Hash::setup(&mut rng).unwrap();
+
+	// inputs longer than a single `Hash` block are accepted
+	let input = [5u8; 300];
+	let digest = var_length_hash(&param, &input).unwrap();
+	let cs = ConstraintSystem::<Fq>::new_ref();
+	let param_var = HashParamVar::new_constant(cs.clone(), &param).unwrap();
+	let input_var = UInt8::new_witness_vec(cs.clone(), &input).unwrap();
+	let digest_var = var_length_hash_gadget(&param_var, &input_var).unwrap();
+	assert_eq!(digest_var.value().unwrap(), digest);
+	assert!(cs.is_satisfied().unwrap());
+
+	// trailing zeros are not absorbed by the padding
+	let padded = [input.as_ref(), [0u8].as_ref()].concat();
+	assert_ne!(var_length_hash(&param, &padded).unwrap(), digest);
+}
+
+#[test]
+fn test_commitment_errors() {
+	let mut rng = ChaCha20Rng::from_seed(COMMIT_PARAM_SEED);
+	let param = param::CommitmentScheme::setup(&mut rng).unwrap();
+	let input = [1u8; 64].to_vec();
+	let randomness = SecretBytes::new([3u8; 32]);
+
+	let commitment = <MantaCrypto as Commitment>::commit(&param, &input, &randomness).unwrap();
+	assert!(<MantaCrypto as Commitment>::check_commitment(
+		&param,
+		&input,
+		&randomness,
+		&commitment
+	)
+	.unwrap());
+	let mut wrong = commitment;
+	wrong[31] ^= 1;
+	assert!(
+		!<MantaCrypto as Commitment>::check_commitment(&param, &input, &randomness, &wrong)
+			.unwrap()
+	);
+
+	assert_eq!(
+		<MantaCrypto as Commitment>::commit(&param, &[0u8; 129].to_vec(), &randomness),
+		Err(CommitmentError::OversizeInput)
+	);
+	assert_eq!(
+		<MantaCrypto as Commitment>::commit(&param, &input, &SecretBytes::new([0xffu8; 32])),
+		Err(CommitmentError::MalformedRandomness)
+	);
+
+	// the errors pass into a `MantaError`
+	let commit = || -> Result<[u8; 32], manta_error::MantaError> {
+		let commitment = <MantaCrypto as Commitment>::commit(&param, &input, &randomness)
+			.map_err(ark_crypto_primitives::Error::from)?;
+		Ok(commitment)
+	};
+	assert_eq!(commit().unwrap(), commitment);
+	let oversize = || -> Result<[u8; 32], manta_error::MantaError> {
+		let commitment =
+			<MantaCrypto as Commitment>::commit(&param, &[0u8; 129].to_vec(), &randomness)
+				.map_err(ark_crypto_primitives::Error::from)?;
+		Ok(commitment)
+	};
+	assert!(oversize().is_err());
+}
+
+#[test]
+fn test_commitment_context() {
+	let mut rng = ChaCha20Rng::from_seed(COMMIT_PARAM_SEED);
+	let param = param::CommitmentScheme::setup(&mut rng).unwrap();
+	let context = CommitmentContext::new(&param);
+
+	let batch: Vec<(Vec<u8>, SecretBytes)> = (0..8u8)
+		.map(|i| ([i; 40].to_vec(), SecretBytes::new([i; 32])))
+		.collect();
+	let commitments = context.commit_batch(&batch).unwrap();
+	for ((input, randomness), commitment) in batch.iter().zip(commitments.iter()) {
+		assert_eq!(
+			<MantaCrypto as Commitment>::commit(&param, input, randomness).unwrap(),
+			*commitment
+		);
+	}
+}
+
+#[test]
+fn test_asset_commitment() {
+	use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+
+	let mut rng = ChaCha20Rng::from_seed(COMMIT_PARAM_SEED);
+	let param = param::CommitmentScheme::setup(&mut rng).unwrap();
+	let k = [9u8; 32];
+	let randomness = SecretBytes::new([4u8; 32]);
+
+	let cm =
+		<MantaCrypto as AssetCommitment>::commit_asset(&param, 1, 100, &k, &randomness).unwrap();
+	assert_ne!(
+		<MantaCrypto as AssetCommitment>::commit_asset(&param, 2, 100, &k, &randomness).unwrap(),
+		cm
+	);
+
+	let cs = ConstraintSystem::<Fq>::new_ref();
+	let param_var = CommitmentParamVar::new_constant(cs.clone(), &param).unwrap();
+	let asset_id = UInt64::new_witness(cs.clone(), || Ok(1)).unwrap();
+	let value = UInt64::new_witness(cs.clone(), || Ok(100)).unwrap();
+	let k_var = UInt8::new_witness_vec(cs.clone(), &k).unwrap();
+	let open = Randomness(Fr::deserialize(randomness.expose().as_ref()).unwrap());
+	let open_var = MantaCoinCommitmentOpenVar::new_witness(cs.clone(), || Ok(open)).unwrap();
+	let cm_var = asset_commitment_gadget(&param_var, &asset_id, &value, &k_var, &open_var).unwrap();
+	let mut cm_bytes = [0u8; 32];
+	cm_var
+		.value()
+		.unwrap()
+		.into_affine()
+		.serialize(cm_bytes.as_mut())
+		.unwrap();
+	assert_eq!(cm_bytes, cm);
+	assert!(cs.is_satisfied().unwrap());
+
+	// mixing assets in a transfer is rejected
+	let other_asset_id = UInt64::new_witness(cs.clone(), || Ok(2)).unwrap();
+	enforce_same_asset(&[asset_id.clone()], &[asset_id, other_asset_id]).unwrap();
+	assert!(!cs.is_satisfied().unwrap());
+
+	// so is mixing assets in the outputs of a transfer with no inputs
+	let cs = ConstraintSystem::<Fq>::new_ref();
+	let asset_id = UInt64::new_witness(cs.clone(), || Ok(1)).unwrap();
+	let same_asset_id = UInt64::new_witness(cs.clone(), || Ok(1)).unwrap();
+	enforce_same_asset(&[], &[asset_id.clone(), same_asset_id]).unwrap();
+	assert!(cs.is_satisfied().unwrap());
+	let other_asset_id = UInt64::new_witness(cs.clone(), || Ok(2)).unwrap();
+	enforce_same_asset(&[], &[asset_id, other_asset_id]).unwrap();
+	assert!(!cs.is_satisfied().unwrap());
+}
+
+#[test]
+fn test_verifiable_encryption() {
+	use ark_ed_on_bls12_381::constraints::EdwardsVar;
+	use ark_ff::{BigInteger, PrimeField};
+	use ark_r1cs_std::{boolean::Boolean, eq::EqGadget};
+
+	let mut rng = ChaCha20Rng::from_seed(POSEIDON_PARAM_SEED);
+	let param = PoseidonHash::setup(&mut rng).unwrap();
+	let (pk, sk) = <MantaCrypto as VerifiableEncryption>::keygen(&mut rng);
+	let (_, other_sk) = <MantaCrypto as VerifiableEncryption>::keygen(&mut rng);
+	let randomness = Fr::from(987654321u64);
+	let value = 12345678;
+
+	let cipher = <MantaCrypto as VerifiableEncryption>::encrypt(&param, &pk, value, &randomness);
+	assert_eq!(
+		<MantaCrypto as VerifiableEncryption>::decrypt(&param, &sk, &cipher),
+		Ok(value)
+	);
+	assert_eq!(
+		<MantaCrypto as VerifiableEncryption>::decrypt(&param, &other_sk, &cipher),
+		Err(EciesError::MalformedPlaintext)
+	);
+
+	let cs = ConstraintSystem::<Fq>::new_ref();
+	let param_var = PoseidonHashParamVar::new_constant(cs.clone(), &param).unwrap();
+	let pk_var = EdwardsVar::new_input(cs.clone(), || Ok(pk)).unwrap();
+	let cipher_var = JubjubCiphertextVar::new_input(cs.clone(), || Ok(cipher)).unwrap();
+	let value_var = UInt64::new_witness(cs.clone(), || Ok(value)).unwrap();
+	let randomness_var =
+		Vec::<Boolean<Fq>>::new_witness(cs.clone(), || Ok(randomness.into_repr().to_bits_le()))
+			.unwrap();
+	verifiable_encryption_gadget(&param_var, &pk_var, &value_var, &randomness_var)
+		.unwrap()
+		.enforce_equal(&cipher_var)
+		.unwrap();
+	assert!(cs.is_satisfied().unwrap());
+
+	// a ciphertext of another value is rejected
+	let cs = ConstraintSystem::<Fq>::new_ref();
+	let param_var = PoseidonHashParamVar::new_constant(cs.clone(), &param).unwrap();
+	let pk_var = EdwardsVar::new_input(cs.clone(), || Ok(pk)).unwrap();
+	let cipher_var = JubjubCiphertextVar::new_input(cs.clone(), || Ok(cipher)).unwrap();
+	let value_var = UInt64::new_witness(cs.clone(), || Ok(value + 1)).unwrap();
+	let randomness_var =
+		Vec::<Boolean<Fq>>::new_witness(cs.clone(), || Ok(randomness.into_repr().to_bits_le()))
+			.unwrap();
+	verifiable_encryption_gadget(&param_var, &pk_var, &value_var, &randomness_var)
+		.unwrap()
+		.enforce_equal(&cipher_var)
+		.unwrap();
+	assert!(!cs.is_satisfied().unwrap());
+}
+
+#[test]
+fn test_shielded_address() {
+	use bech32::{ToBase32, Variant};
+
+	let address = ShieldedAddress {
+		k: [1u8; 32],
+		ecies_pk: [2u8; 32],
+	};
+	let encoded = address.format(Network::Dolphin);
+	assert_eq!(
+		encoded,
+		"dolphin1qqqszqgpqyqszqgpqyqszqgpqyqszqgpqyqszqgpqyqszqgpqyqszqszqgpqyqszqgpqyqszqgpqyqszqgpqyqszqgpqyqszqgpqyqszmvx5pn"
+	);
+	assert_eq!(
+		ShieldedAddress::parse(&encoded),
+		Ok((Network::Dolphin, address))
+	);
+	assert_eq!(
+		ShieldedAddress::parse(&encoded.to_uppercase()),
+		Ok((Network::Dolphin, address))
+	);
+	assert_eq!(
+		ShieldedAddress::parse(&address.format(Network::Manta)),
+		Ok((Network::Manta, address))
+	);
+
+	// a typo is caught by the checksum
+	let typo = encoded.replacen("qyqsz", "qyqsx", 1);
+	assert_eq!(
+		ShieldedAddress::parse(&typo),
+		Err(AddressError::Encoding(bech32::Error::InvalidChecksum))
+	);
+
+	let payload = [
+		[ADDRESS_VERSION].as_ref(),
+		[1u8; 32].as_ref(),
+		[2u8; 32].as_ref(),
+	]
+	.concat();
+	let unknown = bech32::encode("bitcoin", payload.to_base32(), Variant::Bech32m).unwrap();
+	assert_eq!(
+		ShieldedAddress::parse(&unknown),
+		Err(AddressError::UnknownNetwork)
+	);
+	let legacy = bech32::encode("manta", payload.to_base32(), Variant::Bech32).unwrap();
+	assert_eq!(
+		ShieldedAddress::parse(&legacy),
+		Err(AddressError::InvalidVariant)
+	);
+	let short = bech32::encode("manta", (&payload[..33]).to_base32(), Variant::Bech32m).unwrap();
+	assert_eq!(
+		ShieldedAddress::parse(&short),
+		Err(AddressError::InvalidLength)
+	);
+	let mut future = payload.clone();
+	future[0] = 1;
+	let future = bech32::encode("manta", future.to_base32(), Variant::Bech32m).unwrap();
+	assert_eq!(
+		ShieldedAddress::parse(&future),
+		Err(AddressError::UnsupportedVersion(1))
+	);
+
+	// the errors pass into a `MantaError`
+	let parse = |s: &str| -> Result<ShieldedAddress, manta_error::MantaError> {
+		let (_, address) = ShieldedAddress::parse(s).map_err(ark_crypto_primitives::Error::from)?;
+		Ok(address)
+	};
+	assert_eq!(parse(&encoded).unwrap(), address);
+	assert!(parse(&typo).is_err());
+}
+
+/// Test vectors of the key derivation, for the seed `00 01 .. 1f`.
+#[test]
+fn test_key_derivation() {
+	let from_hex = |s: &str| -> Vec<u8> {
+		(0..s.len())
+			.step_by(2)
+			.map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+			.collect()
+	};
+	// the test vectors of the docs of `key_derivation`
+	let seed: Vec<u8> = (0u8..32).collect();
+
+	let master = ExtendedSpendingKey::master(&seed).unwrap();
+	assert_eq!(
+		master.key.expose().to_vec(),
+		from_hex("897549e6199199d64ef90d841a863af6976e488e13fcd1a17f814fb9404a674e")
+	);
+	assert_eq!(
+		master.chain_code.expose().to_vec(),
+		from_hex("6abeacd084abfac27a5444a3bf748a9f37114a7973d67813c53ce2c625bf32e6")
+	);
+
+	// m/44'/611'/0'
+	let path = parse_derivation_path("m/44'/611'/0'").unwrap();
+	assert_eq!(path, [44, 611, 0]);
+	let account = master.derive_path(&path).unwrap();
+	assert_eq!(account.depth, 3);
+	assert_eq!(account.child_index, HARDENED_OFFSET);
+	assert_eq!(
+		account.key.expose().to_vec(),
+		from_hex("57a4cc0ecbc7995ba2df074597574df9a8f0d0bda2b78a14ccf90f1061e74197")
+	);
+	assert_eq!(
+		account.chain_code.expose().to_vec(),
+		from_hex("bf21c54c0b9b95ad33662b4d0254bcbc40d15685866a4764468508f53174ab83")
+	);
+
+	let viewing_key = account.viewing_key();
+	assert_eq!(
+		viewing_key.0.expose().to_vec(),
+		from_hex("a967ab6e5610b0a59c7fccc87c446820ad8844ec9db46ecd6524f709b7663da6")
+	);
+	let (ecies_pk, ecies_sk) = viewing_key.ecies_keypair();
+	assert_eq!(
+		ecies_pk.to_vec(),
+		from_hex("2fdc1be4243db871bf75bbe2d0f165a1303b46cd640b3ce5256a730b36278c7f")
+	);
+	assert_eq!(
+		ecies_sk.expose().to_vec(),
+		from_hex("f0f5a41867fbaf031fac7ca0c97e8c1e80432048468bdb517736d9629a719f63")
+	);
+	assert_eq!(
+		viewing_key.outgoing_viewing_key().expose().to_vec(),
+		from_hex("670399773bff34ebf9eaa83d034368cf94044550785cb0ad79a8a8b17f5d04f7")
+	);
+
+	assert_eq!(
+		parse_derivation_path("m/0"),
+		Err(DerivationError::MalformedPath)
+	);
+	assert_eq!(
+		master.child(HARDENED_OFFSET),
+		Err(DerivationError::IndexOutOfRange(HARDENED_OFFSET))
+	);
+	assert_eq!(
+		ExtendedSpendingKey::master(&[0u8; 8]),
+		Err(DerivationError::InvalidSeedLength)
+	);
+
+	// the errors pass into a `MantaError`
+	let derive = |path: &str| -> Result<ExtendedSpendingKey, manta_error::MantaError> {
+		let path = parse_derivation_path(path).map_err(ark_crypto_primitives::Error::from)?;
+		let key = master
+			.derive_path(&path)
+			.map_err(ark_crypto_primitives::Error::from)?;
+		Ok(key)
+	};
+	assert_eq!(derive("m/44'/611'/0'").unwrap(), account);
+	assert!(derive("m/0").is_err());
+}
+
+#[test]
+fn test_secret() {
+	let secret = SecretBytes::new([5u8; 32]);
+	assert_eq!(ark_std::format!("{:?}", secret), "Secret([REDACTED])");
+	assert_eq!(secret.clone(), secret);
+	assert_ne!(SecretBytes::new([6u8; 32]), secret);
+
+	let note = NotePlaintext {
+		value: 1,
+		asset_id: 1,
+		randomness: secret,
+		rho: [4u8; 32],
+		memo: Vec::new(),
+	};
+	assert!(!ark_std::format!("{:?}", note).contains("5, 5"));
+}
+
+#[test]
+fn test_stealth_address() {
+	use ark_ed_on_bls12_381::EdwardsProjective;
+	use ark_ff::PrimeField;
+
+	let mut rng = ChaCha20Rng::from_seed([8u8; 32]);
+	let receiver = StealthSpendingKey::new(&mut rng);
+	let other = StealthSpendingKey::new(&mut rng);
+	let address = receiver.address();
+
+	let payment = address.derive_one_time(&mut rng);
+	let next_payment = address.derive_one_time(&mut rng);
+	assert_ne!(payment.one_time_pk, next_payment.one_time_pk);
+	assert_ne!(payment.commitment_key(), next_payment.commitment_key());
+
+	assert!(receiver.viewing_key().recognize(&payment));
+	assert!(receiver.viewing_key().recognize(&next_payment));
+	assert!(!other.viewing_key().recognize(&payment));
+
+	// the one-time secret key opens the one-time public key
+	let sk = receiver.one_time_sk(&payment).unwrap();
+	let sk = Fr::from_le_bytes_mod_order(sk.expose());
+	assert_eq!(
+		EdwardsProjective::prime_subgroup_generator()
+			.mul(sk.into_repr())
+			.into_affine(),
+		payment.one_time_pk
+	);
+	assert!(other.one_time_sk(&payment).is_none());
+}
+
+#[cfg(feature = "pq-hybrid")]
+#[test]
+fn manta_hybrid_note_encryption() {
+	let mut rng = ChaCha20Rng::from_seed([1u8; 32]);
+	let (pk, sk) = <HybridNoteEncryption as Ecies>::keygen(&mut rng);
+	let (other_pk, _) = <HybridNoteEncryption as Ecies>::keygen(&mut rng);
+	let note = NotePlaintext {
+		value: 12345678,
+		asset_id: 1,
+		randomness: SecretBytes::new([3u8; 32]),
+		rho: [4u8; 32],
+		memo: b"harvest now, decrypt never".to_vec(),
+	};
+
+	let cipher = <HybridNoteEncryption as Ecies>::encrypt(&pk, &note, &mut rng);
+	assert_eq!(cipher[0], HYBRID_NOTE_CIPHERTEXT_VERSION);
+	assert_eq!(
+		<HybridNoteEncryption as Ecies>::decrypt(&sk, &cipher),
+		Ok(note.clone())
+	);
+
+	// a private key that is rebuilt from its seeds decrypts alike
+	let restored = HybridPrivateKey::new(sk.x25519().clone(), sk.ml_kem_seed().clone());
+	assert_eq!(
+		<HybridNoteEncryption as Ecies>::decrypt(&restored, &cipher),
+		Ok(note.clone())
+	);
+
+	// both exchanges are bound into the key
+	let mut tampered = cipher.clone();
+	tampered[1] ^= 1;
+	assert_eq!(
+		<HybridNoteEncryption as Ecies>::decrypt(&sk, &tampered),
+		Err(EciesError::AuthenticationFailure)
+	);
+	let mut tampered = cipher.clone();
+	tampered[100] ^= 1;
+	assert_eq!(
+		<HybridNoteEncryption as Ecies>::decrypt(&sk, &tampered),
+		Err(EciesError::AuthenticationFailure)
+	);
+	let other_cipher = <HybridNoteEncryption as Ecies>::encrypt(&other_pk, &note, &mut rng);
+	assert_eq!(
+		<HybridNoteEncryption as Ecies>::decrypt(&sk, &other_cipher),
+		Err(EciesError::AuthenticationFailure)
+	);
+
+	// classical note ciphertexts are not hybrid ciphertexts
+	let classical = <NoteEncryption as Ecies>::encrypt(&pk.x25519, &note, &mut rng);
+	assert_eq!(
+		<HybridNoteEncryption as Ecies>::decrypt(&sk, &classical),
+		Err(EciesError::UnsupportedVersion(NOTE_CIPHERTEXT_VERSION))
+	);
+	assert_eq!(
+		<HybridNoteEncryption as Ecies>::decrypt(&sk, &cipher[..64].to_vec()),
+		Err(EciesError::MalformedCiphertext)
+	);
+}
+
+#[test]
+fn test_incremental_merkle_tree() {
+	use ark_serialize::CanonicalSerialize;
+
+	let mut rng = ChaCha20Rng::from_seed(HASH_PARAM_SEED);
+	let param = Hash::setup(&mut rng).unwrap();
+
+	// the frontier has the roots of the ledger tree after every append
+	let leaves: Vec<[u8; 32]> = (0..9u8).map(|i| [i; 32]).collect();
+	let mut tree = LedgerIncrementalMerkleTree::new(param.clone()).unwrap();
+	for n in 0..=leaves.len() {
+		if n > 0 {
+			assert_eq!(tree.append(&leaves[n - 1]).unwrap(), n - 1);
+		}
+		let mut root = [0u8; 32];
+		tree.root().unwrap().serialize(root.as_mut()).unwrap();
+		assert_eq!(
+			root,
+			<MantaCrypto as MerkleTree>::root(param.clone(), &leaves[..n]).unwrap()
+		);
+	}
+
+	// a small tree is full after 2^(HEIGHT - 1) leaves
+	let mut tree = LedgerIncrementalMerkleTreeOfDepth::<3>::new(param.clone()).unwrap();
+	for leaf in &leaves[..4] {
+		tree.append(leaf).unwrap();
+		let expected =
+			LedgerMerkleTreeOfDepth::<3>::new(param.clone(), &leaves[..tree.len()]).unwrap();
+		assert_eq!(tree.root().unwrap(), expected.root());
+	}
+	assert_eq!(tree.append(&leaves[4]), Err(MerkleTreeError::TreeFull));
+}
+
+#[test]
+fn test_membership_path() {
+	use ark_r1cs_std::prelude::{Boolean, EqGadget};
+
+	let mut rng = ChaCha20Rng::from_seed(HASH_PARAM_SEED);
+	let param = Hash::setup(&mut rng).unwrap();
+	let leaves: Vec<[u8; 32]> = (0..5u8).map(|i| [i; 32]).collect();
+	let root = <MantaCrypto as MerkleTree>::root(param.clone(), &leaves).unwrap();
+
+	// every leaf has a path to the root, that survives serialization
+	for (index, leaf) in leaves.iter().enumerate() {
+		let path = <MantaCrypto as MerkleTreePath>::membership_path(param.clone(), &leaves, index)
+			.unwrap();
+		let bytes = <MantaCrypto as MerkleTreePath>::path_to_bytes(&path).unwrap();
+		let path = <MantaCrypto as MerkleTreePath>::path_from_bytes(&bytes).unwrap();
+		assert!(
+			<MantaCrypto as MerkleTreePath>::verify_path(param.clone(), &root, leaf, &path)
+				.unwrap()
+		);
+	}
+
+	// a path binds the leaf and its index
+	let mut path =
+		<MantaCrypto as MerkleTreePath>::membership_path(param.clone(), &leaves, 2).unwrap();
+	assert!(
+		!<MantaCrypto as MerkleTreePath>::verify_path(param.clone(), &root, &leaves[3], &path)
+			.unwrap()
+	);
+	path.index = 3;
+	assert!(
+		!<MantaCrypto as MerkleTreePath>::verify_path(param.clone(), &root, &leaves[2], &path)
+			.unwrap()
+	);
+	assert!(<MantaCrypto as MerkleTreePath>::membership_path(param.clone(), &leaves, 5).is_err());
+
+	// the gadget agrees with the native verification
+	path.index = 2;
+	let cs = ConstraintSystem::<Fq>::new_ref();
+	let param_var = HashParamVar::new_constant(cs.clone(), &param).unwrap();
+	let root_digest = LedgerIncrementalMerkleTree::from_leaves(param.clone(), &leaves)
+		.unwrap()
+		.root()
+		.unwrap();
+	let root_var = HashOutputVar::new_input(cs.clone(), || Ok(root_digest)).unwrap();
+	let path_var = LedgerMembershipPathVar::new_witness(cs.clone(), || Ok(&path)).unwrap();
+	let leaf_var = UInt8::new_witness_vec(cs.clone(), &leaves[2]).unwrap();
+	path_var
+		.check_membership(&param_var, &root_var, &leaf_var)
+		.unwrap()
+		.enforce_equal(&Boolean::TRUE)
+		.unwrap();
+	assert!(cs.is_satisfied().unwrap());
+}
+
+#[test]
+fn test_sparse_merkle_tree() {
+	use ark_r1cs_std::prelude::{Boolean, EqGadget};
+	use ark_relations::r1cs::{SynthesisError, SynthesisMode};
+
+	let mut rng = ChaCha20Rng::from_seed(HASH_PARAM_SEED);
+	let param = Hash::setup(&mut rng).unwrap();
+	let mut tree = VoidNumberTree::new(param.clone()).unwrap();
+	let void_numbers = [[1u8; 32], [2u8; 32], [0u8; 32]];
+	for void_number in void_numbers.iter() {
+		assert!(tree.insert(void_number).unwrap());
+	}
+	assert!(!tree.insert(&void_numbers[0]).unwrap());
+	let root = tree.root();
+
+	// every void number proves its membership, but not its non-membership
+	for void_number in void_numbers.iter() {
+		let proof = tree.prove(void_number);
+		let mut bytes = Vec::new();
+		proof.serialize(&mut bytes).unwrap();
+		let proof = VoidNumberProof::deserialize(bytes.as_slice()).unwrap();
+		assert!(proof.verify_membership(&param, &root, void_number).unwrap());
+		assert!(!proof
+			.verify_non_membership(&param, &root, void_number)
+			.unwrap());
+	}
+	let fresh = [3u8; 32];
+	let proof = tree.prove(&fresh);
+	assert!(proof.verify_non_membership(&param, &root, &fresh).unwrap());
+	assert!(!proof.verify_membership(&param, &root, &fresh).unwrap());
+	let mut truncated = proof;
+	truncated.siblings.pop();
+	assert_eq!(
+		truncated.verify_non_membership(&param, &root, &fresh),
+		Err(MerkleTreeError::MalformedProof)
+	);
+
+	// the gadget agrees with the native verification, over a configurable hash
+	let mut rng = ChaCha20Rng::from_seed(POSEIDON_PARAM_SEED);
+	let param = PoseidonHash::setup(&mut rng).unwrap();
+	let mut tree = SparseMerkleTree::<PoseidonHash>::new(param.clone()).unwrap();
+	tree.insert(&void_numbers[0]).unwrap();
+	let proof = tree.prove(&fresh);
+	let cs = ConstraintSystem::<Fq>::new_ref();
+	let param_var = PoseidonHashParamVar::new_constant(cs.clone(), &param).unwrap();
+	let root_var = PoseidonHashOutputVar::new_input(cs.clone(), || Ok(tree.root())).unwrap();
+	let proof_var =
+		SparseMerkleProofVar::<PoseidonHash, PoseidonHashVar>::new_witness(cs.clone(), || {
+			Ok(&proof)
+		})
+		.unwrap();
+	let key_var = UInt8::new_witness_vec(cs.clone(), &fresh).unwrap();
+	proof_var
+		.check_non_membership(&param_var, &root_var, &key_var)
+		.unwrap()
+		.enforce_equal(&Boolean::TRUE)
+		.unwrap();
+	let witness_constraints = cs.num_constraints();
+	assert!(cs.is_satisfied().unwrap());
+	assert!(!proof_var
+		.check_membership(&param_var, &root_var, &key_var)
+		.unwrap()
+		.value()
+		.unwrap());
+
+	// a constant membership hashes a single leaf, unlike a witness one
+	let constraints = cs.num_constraints();
+	proof_var
+		.check_non_membership(&param_var, &root_var, &key_var)
+		.unwrap();
+	let constant_constraints = cs.num_constraints() - constraints;
+	let is_member = Boolean::new_witness(cs.clone(), || Ok(false)).unwrap();
+	let constraints = cs.num_constraints();
+	proof_var.root(&param_var, &key_var, &is_member).unwrap();
+	assert!(cs.num_constraints() - constraints > constant_constraints);
+
+	// the gadget is allocated at setup, where the proof is missing, in the same shape
+	let cs = ConstraintSystem::<Fq>::new_ref();
+	cs.set_mode(SynthesisMode::Setup);
+	let param_var = PoseidonHashParamVar::new_constant(cs.clone(), &param).unwrap();
+	let root_var = PoseidonHashOutputVar::new_input(cs.clone(), || Ok(tree.root())).unwrap();
+	let proof_var =
+		SparseMerkleProofVar::<PoseidonHash, PoseidonHashVar>::new_witness(cs.clone(), || {
+			Err::<SparseMerkleProof<PoseidonHash>, _>(SynthesisError::AssignmentMissing)
+		})
+		.unwrap();
+	let key_var = UInt8::new_witness_vec(cs.clone(), &fresh).unwrap();
+	proof_var
+		.check_non_membership(&param_var, &root_var, &key_var)
+		.unwrap()
+		.enforce_equal(&Boolean::TRUE)
+		.unwrap();
+	assert_eq!(cs.num_constraints(), witness_constraints);
+}
+
+#[test]
+fn test_witness_tracker() {
+	let mut rng = ChaCha20Rng::from_seed(HASH_PARAM_SEED);
+	let param = Hash::setup(&mut rng).unwrap();
+	let leaves: Vec<[u8; 32]> = (0..11u8).map(|i| [i; 32]).collect();
+
+	// the tracked paths are the paths of the full tree after every append
+	let mut tracker = LedgerWitnessTracker::new(param.clone()).unwrap();
+	for (index, leaf) in leaves.iter().enumerate() {
+		if index % 3 == 0 {
+			assert_eq!(tracker.append_marked(leaf).unwrap(), index);
+		} else {
+			assert_eq!(tracker.append(leaf).unwrap(), index);
+		}
+		for marked in tracker.marked() {
+			assert_eq!(
+				tracker.path(marked).unwrap(),
+				LedgerMembershipPath::from_leaves(&param, &leaves[..=index], marked).unwrap()
+			);
+		}
+	}
+	let root = tracker.tree().root().unwrap();
+	assert!(tracker
+		.path(9)
+		.unwrap()
+		.verify(&param, &root, &leaves[9])
+		.unwrap());
+
+	// only marked leaves have paths
+	assert!(tracker.unmark(3));
+	assert!(!tracker.unmark(4));
+	assert_eq!(tracker.path(3), Err(MerkleTreeError::InvalidLeafIndex));
+	assert_eq!(tracker.marked().collect::<Vec<_>>(), [0, 6, 9]);
+
+	// a leaf is marked from its path mid-stream, and its path stays current
+	let mut tracker = LedgerWitnessTracker::new(param.clone()).unwrap();
+	for leaf in leaves[..6].iter() {
+		tracker.append(leaf).unwrap();
+	}
+	for (index, leaf) in leaves[..6].iter().enumerate() {
+		let path = LedgerMembershipPath::from_leaves(&param, &leaves[..6], index).unwrap();
+		assert!(tracker.mark(leaf, &path).unwrap());
+		assert_eq!(tracker.path(index).unwrap(), path);
+	}
+	for (index, leaf) in leaves.iter().enumerate().skip(6) {
+		tracker.append(leaf).unwrap();
+		for marked in tracker.marked() {
+			assert_eq!(
+				tracker.path(marked).unwrap(),
+				LedgerMembershipPath::from_leaves(&param, &leaves[..=index], marked).unwrap()
+			);
+		}
+	}
+
+	// only the current path of a leaf marks it
+	let stale = LedgerMembershipPath::from_leaves(&param, &leaves[..6], 2).unwrap();
+	assert!(tracker.unmark(2));
+	assert!(!tracker.mark(&leaves[2], &stale).unwrap());
+	let path = LedgerMembershipPath::from_leaves(&param, &leaves, 2).unwrap();
+	assert!(!tracker.mark(&leaves[3], &path).unwrap());
+	assert_eq!(tracker.path(2), Err(MerkleTreeError::InvalidLeafIndex));
+}
+
+#[test]
+fn test_checkpointed_merkle_tree() {
+	let mut rng = ChaCha20Rng::from_seed(HASH_PARAM_SEED);
+	let param = Hash::setup(&mut rng).unwrap();
+	let leaves: Vec<[u8; 32]> = (0..6u8).map(|i| [i; 32]).collect();
+	let mut tree = LedgerCheckpointedMerkleTree::new(param, 2, 4).unwrap();
+
+	// three blocks of two leaves each; the window holds the last two roots
+	let mut roots = Vec::new();
+	for block in leaves.chunks(2) {
+		tree.checkpoint();
+		for leaf in block {
+			tree.append(leaf).unwrap();
+		}
+		roots.push(tree.record_root().unwrap());
+	}
+	assert!(!tree.is_recent_root(&roots[0]));
+	assert!(tree.is_recent_root(&roots[1]));
+	assert!(tree.is_recent_root(&roots[2]));
+
+	// rewinding the last block restores the root and the window exactly
+	tree.rewind().unwrap();
+	assert_eq!(tree.tree().len(), 4);
+	assert_eq!(tree.root().unwrap(), roots[1]);
+	assert!(tree.is_recent_root(&roots[0]));
+	assert!(!tree.is_recent_root(&roots[2]));
+
+	// the block is replayed to the same root
+	tree.append(&leaves[4]).unwrap();
+	tree.append(&leaves[5]).unwrap();
+	assert_eq!(tree.record_root().unwrap(), roots[2]);
+
+	assert_eq!(tree.checkpoint_count(), 2);
+	tree.rewind().unwrap();
+	tree.rewind().unwrap();
+	assert!(tree.tree().is_empty());
+	assert_eq!(tree.rewind(), Err(MerkleTreeError::NoCheckpoint));
+}
+
+#[test]
+fn test_stored_merkle_tree() {
+	let mut rng = ChaCha20Rng::from_seed(HASH_PARAM_SEED);
+	let param = Hash::setup(&mut rng).unwrap();
+	let leaves: Vec<[u8; 32]> = (0..7u8).map(|i| [i; 32]).collect();
+	let mut tree =
+		LedgerStoredMerkleTree::open(param.clone(), MemoryNodeStorage::default()).unwrap();
+	let mut expected = LedgerIncrementalMerkleTree::new(param.clone()).unwrap();
+
+	// the stored tree has the roots and the paths of the ledger tree
+	for (n, leaf) in leaves.iter().enumerate() {
+		assert_eq!(tree.append(leaf).unwrap(), n);
+		expected.append(leaf).unwrap();
+		assert_eq!(tree.root().unwrap(), expected.root().unwrap());
+	}
+	for index in 0..leaves.len() {
+		assert_eq!(
+			tree.path(index).unwrap(),
+			LedgerMembershipPath::from_leaves(&param, &leaves, index).unwrap()
+		);
+	}
+	assert_eq!(tree.path(7), Err(MerkleTreeError::InvalidLeafIndex));
+
+	// the tree is reopened from its storage, and keeps growing
+	let root = tree.root().unwrap();
+	let mut tree = LedgerStoredMerkleTree::open(param.clone(), tree.into_storage()).unwrap();
+	assert_eq!(tree.len(), 7);
+	assert_eq!(tree.root().unwrap(), root);
+	tree.append(&[7u8; 32]).unwrap();
+	expected.append(&[7u8; 32]).unwrap();
+	assert_eq!(tree.root().unwrap(), expected.root().unwrap());
+
+	#[cfg(feature = "std")]
+	{
+		let dir = std::env::temp_dir().join(std::format!(
+			"manta-crypto-test-stored-merkle-tree-{}",
+			std::process::id()
+		));
+		let _ = std::fs::remove_dir_all(&dir);
+		let storage = FileNodeStorage::<MerkleTreeParams>::open(&dir).unwrap();
+		let mut tree = LedgerStoredMerkleTree::open(param.clone(), storage).unwrap();
+		for leaf in leaves.iter() {
+			tree.append(leaf).unwrap();
+		}
+		drop(tree);
+		let storage = FileNodeStorage::<MerkleTreeParams>::open(&dir).unwrap();
+		let tree = LedgerStoredMerkleTree::open(param.clone(), storage).unwrap();
+		assert_eq!(tree.len(), 7);
+		assert_eq!(tree.root().unwrap(), root);
+		drop(tree);
+
+		// a torn write of the next leaf is not part of the reopened tree
+		{
+			use std::io::Write;
+			let path = dir.join("level_0.bin");
+			let record_bytes = std::fs::metadata(&path).unwrap().len() as usize / 7;
+			let mut torn = ark_std::vec![0xffu8; record_bytes];
+			torn[0] = 1;
+			let mut file = std::fs::OpenOptions::new()
+				.append(true)
+				.open(&path)
+				.unwrap();
+			file.write_all(&torn).unwrap();
+		}
+		let storage = FileNodeStorage::<MerkleTreeParams>::open(&dir).unwrap();
+		let mut tree = LedgerStoredMerkleTree::open(param, storage).unwrap();
+		assert_eq!(tree.len(), 7);
+		assert_eq!(tree.root().unwrap(), root);
+		tree.append(&[7u8; 32]).unwrap();
+		assert_eq!(tree.root().unwrap(), expected.root().unwrap());
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+}
+
+#[test]
+fn test_merkle_tree_depth() {
+	use ark_crypto_primitives::merkle_tree::Config;
+	use ark_r1cs_std::prelude::{Boolean, EqGadget};
+
+	let mut rng = ChaCha20Rng::from_seed(HASH_PARAM_SEED);
+	let param = Hash::setup(&mut rng).unwrap();
+	let leaves: Vec<[u8; 32]> = (0..5u8).map(|i| [i; 32]).collect();
+
+	// the default depth is the one of the ledger tree
+	assert_eq!(<MerkleTreeParams as Config>::HEIGHT, TREE_DEPTH);
+	assert_eq!(<PoseidonMerkleTreeParams as Config>::HEIGHT, TREE_DEPTH);
+	assert_eq!(<MerkleTreeParams as TreeHashing>::HEIGHT, TREE_DEPTH);
+	assert_eq!(<TaggedMerkleTreeParams as TreeHashing>::HEIGHT, TREE_DEPTH);
+
+	// a smaller tree has shorter paths, and its own roots
+	let tree =
+		LedgerIncrementalMerkleTreeOfDepth::<8>::from_leaves(param.clone(), &leaves).unwrap();
+	let root = tree.root().unwrap();
+	assert_eq!(
+		root,
+		LedgerMerkleTreeOfDepth::<8>::new(param.clone(), &leaves)
+			.unwrap()
+			.root()
+	);
+	assert_ne!(
+		root,
+		LedgerIncrementalMerkleTree::from_leaves(param.clone(), &leaves)
+			.unwrap()
+			.root()
+			.unwrap()
+	);
+	let path = LedgerMembershipPathOfDepth::<8>::from_leaves(&param, &leaves, 4).unwrap();
+	assert_eq!(path.siblings.len(), 7);
+	assert!(path.verify(&param, &root, &leaves[4]).unwrap());
+
+	// the gadget follows the depth of the path
+	let cs = ConstraintSystem::<Fq>::new_ref();
+	let param_var = HashParamVar::new_constant(cs.clone(), &param).unwrap();
+	let root_var = HashOutputVar::new_input(cs.clone(), || Ok(root)).unwrap();
+	let path_var =
+		LedgerMembershipPathVarOfDepth::<8>::new_witness(cs.clone(), || Ok(&path)).unwrap();
+	let leaf_var = UInt8::new_witness_vec(cs.clone(), &leaves[4]).unwrap();
+	path_var
+		.check_membership(&param_var, &root_var, &leaf_var)
+		.unwrap()
+		.enforce_equal(&Boolean::TRUE)
+		.unwrap();
+	assert!(cs.is_satisfied().unwrap());
+}
+
+#[test]
+fn test_tagged_merkle_tree() {
+	use crate::merkle_tree::hash_leaf;
+	use ark_ff::ToBytes;
+	use ark_r1cs_std::prelude::{Boolean, EqGadget};
+	use ark_serialize::CanonicalSerialize;
+
+	let mut rng = ChaCha20Rng::from_seed(HASH_PARAM_SEED);
+	let param = Hash::setup(&mut rng).unwrap();
+	let leaves: Vec<[u8; 32]> = (0..4u8).map(|i| [i; 32]).collect();
+
+	// the legacy root is the root of the ledger tree, and the tagged root differs
+	let legacy =
+		<MantaCrypto as MerkleTree>::versioned_root(param.clone(), &leaves, TreeVersion::Legacy)
+			.unwrap();
+	let mut bytes = [0u8; 32];
+	legacy.root.serialize(bytes.as_mut()).unwrap();
+	assert_eq!(
+		bytes,
+		<MantaCrypto as MerkleTree>::root(param.clone(), &leaves).unwrap()
+	);
+	let tagged =
+		<MantaCrypto as MerkleTree>::versioned_root(param.clone(), &leaves, TreeVersion::Tagged)
+			.unwrap();
+	assert_ne!(tagged.root, legacy.root);
+
+	// a tree that predates the versions supports none of them
+	struct UnversionedTree;
+	impl MerkleTree for UnversionedTree {
+		type Param = HashParam;
+		type Leaf = [u8; 32];
+		type Root = [u8; 32];
+		type Tree = LedgerMerkleTree;
+
+		fn build_tree(
+			hash_param: Self::Param,
+			leaves: &[Self::Leaf],
+		) -> Result<Self::Tree, manta_error::MantaError> {
+			<MantaCrypto as MerkleTree>::build_tree(hash_param, leaves)
+		}
+
+		fn root(
+			hash_param: Self::Param,
+			leaves: &[Self::Leaf],
+		) -> Result<Self::Root, manta_error::MantaError> {
+			<MantaCrypto as MerkleTree>::root(hash_param, leaves)
+		}
+	}
+	assert!(<UnversionedTree as MerkleTree>::versioned_root(
+		param.clone(),
+		&leaves,
+		TreeVersion::Legacy
+	)
+	.is_err());
+
+	// a versioned root survives serialization, and an unknown version is rejected
+	let mut bytes = Vec::new();
+	MantaSerDes::serialize(&tagged, &mut bytes).unwrap();
+	assert_eq!(
+		<VersionedRoot as MantaSerDes>::deserialize(bytes.as_slice()).unwrap(),
+		tagged
+	);
+	bytes[0] = 2;
+	assert!(<VersionedRoot as MantaSerDes>::deserialize(bytes.as_slice()).is_err());
+
+	// on the legacy tree, the children of an inner node pass for a 128 bytes leaf
+	let mut fake_leaf = Vec::new();
+	hash_leaf::<MerkleTreeParams, _>(&param, &leaves[0])
+		.unwrap()
+		.write(&mut fake_leaf)
+		.unwrap();
+	hash_leaf::<MerkleTreeParams, _>(&param, &leaves[1])
+		.unwrap()
+		.write(&mut fake_leaf)
+		.unwrap();
+	assert_eq!(fake_leaf.len(), 128);
+	let path = LedgerMembershipPath::from_leaves(&param, &leaves, 0).unwrap();
+	let fake_path = LedgerMembershipPath {
+		index: 0,
+		siblings: path.siblings[1..].to_vec(),
+	};
+	assert!(fake_path.verify(&param, &legacy.root, &fake_leaf).unwrap());
+
+	// but not on the tagged tree, whose inner nodes hash the x coordinates
+	// of their children
+	let path = TaggedLedgerMembershipPath::from_leaves(&param, &leaves, 0).unwrap();
+	assert!(path.verify(&param, &tagged.root, &leaves[0]).unwrap());
+	let mut fake_leaf = Vec::new();
+	hash_leaf::<TaggedMerkleTreeParams, _>(&param, &leaves[0])
+		.unwrap()
+		.x
+		.write(&mut fake_leaf)
+		.unwrap();
+	hash_leaf::<TaggedMerkleTreeParams, _>(&param, &leaves[1])
+		.unwrap()
+		.x
+		.write(&mut fake_leaf)
+		.unwrap();
+	let fake_path = TaggedLedgerMembershipPath {
+		index: 0,
+		siblings: path.siblings[1..].to_vec(),
+	};
+	assert!(!fake_path.verify(&param, &tagged.root, &fake_leaf).unwrap());
+
+	// the tagged tree has the roots and the paths of its leaves at every size
+	let mut tree = TaggedLedgerMerkleTree::new(param.clone()).unwrap();
+	for (n, leaf) in leaves.iter().enumerate() {
+		tree.append(leaf).unwrap();
+		let root = tree.root().unwrap();
+		for (index, leaf) in leaves[..=n].iter().enumerate() {
+			let path =
+				TaggedLedgerMembershipPath::from_leaves(&param, &leaves[..=n], index).unwrap();
+			assert!(path.verify(&param, &root, leaf).unwrap());
+		}
+	}
+	assert_eq!(tree.root().unwrap(), tagged.root);
+
+	// the gadget hashes with the tags of the tree
+	let path = TaggedLedgerMembershipPath::from_leaves(&param, &leaves, 3).unwrap();
+	let cs = ConstraintSystem::<Fq>::new_ref();
+	let param_var = HashParamVar::new_constant(cs.clone(), &param).unwrap();
+	let root_var = HashOutputVar::new_input(cs.clone(), || Ok(tagged.root)).unwrap();
+	let path_var = TaggedLedgerMembershipPathVar::new_witness(cs.clone(), || Ok(&path)).unwrap();
+	let leaf_var = UInt8::new_witness_vec(cs.clone(), &leaves[3]).unwrap();
+	path_var
+		.check_membership(&param_var, &root_var, &leaf_var)
+		.unwrap()
+		.enforce_equal(&Boolean::TRUE)
+		.unwrap();
+	assert!(cs.is_satisfied().unwrap());
+}
+
+#[test]
+fn test_membership_path_shape() {
+	use ark_r1cs_std::prelude::{Boolean, EqGadget};
+
+	let mut rng = ChaCha20Rng::from_seed(HASH_PARAM_SEED);
+	let param = Hash::setup(&mut rng).unwrap();
+	let leaves: Vec<[u8; 32]> = (0..6u8).map(|i| [i; 32]).collect();
+
+	// a tree of 4 levels has paths of 3 siblings up to 4 leaves, and of 4 beyond,
+	// which all fit a single circuit shape, that of the default path
+	let check = |path: &LedgerMembershipPathOfDepth<4>, leaves: &[[u8; 32]]| {
+		let root = LedgerIncrementalMerkleTreeOfDepth::<4>::from_leaves(param.clone(), leaves)
+			.unwrap()
+			.root()
+			.unwrap();
+		let cs = ConstraintSystem::<Fq>::new_ref();
+		let param_var = HashParamVar::new_constant(cs.clone(), &param).unwrap();
+		let root_var = HashOutputVar::new_input(cs.clone(), || Ok(root)).unwrap();
+		let path_var =
+			LedgerMembershipPathVarOfDepth::<4>::new_witness(cs.clone(), || Ok(path)).unwrap();
+		let leaf_var = UInt8::new_witness_vec(cs.clone(), &leaves[path.index]).unwrap();
+		path_var
+			.check_membership(&param_var, &root_var, &leaf_var)
+			.unwrap()
+			.enforce_equal(&Boolean::TRUE)
+			.unwrap();
+		(cs.is_satisfied().unwrap(), cs.num_constraints())
+	};
+	let half = LedgerMembershipPathOfDepth::<4>::from_leaves(&param, &leaves[..4], 3).unwrap();
+	assert_eq!(half.siblings.len(), 3);
+	let full = LedgerMembershipPathOfDepth::<4>::from_leaves(&param, &leaves, 5).unwrap();
+	assert_eq!(full.siblings.len(), 4);
+	let (half_satisfied, half_constraints) = check(&half, &leaves[..4]);
+	let (full_satisfied, full_constraints) = check(&full, &leaves);
+	let (_, default_constraints) = check(&Default::default(), &leaves[..1]);
+	assert!(half_satisfied && full_satisfied);
+	assert_eq!(half_constraints, full_constraints);
+	assert_eq!(half_constraints, default_constraints);
+
+	// a path of another length is rejected
+	let cs = ConstraintSystem::<Fq>::new_ref();
+	let mut short = half;
+	short.siblings.pop();
+	assert!(LedgerMembershipPathVarOfDepth::<4>::new_witness(cs, || Ok(short)).is_err());
+}
+
 // this is a placeholder
 // todo: write more tests
 // 1. serdes
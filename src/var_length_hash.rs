@@ -0,0 +1,114 @@
+// Copyright 2019-2021 Manta Network.
+// This file is part of manta-crypto.
+//
+// manta-crypto is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// manta-crypto is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with manta-crypto.  If not, see <http://www.gnu.org/licenses/>.
+
+//! This file implements a variable-length hash mode on top of the
+//! fixed-length Pedersen `Hash`, together with its gadget.
+//!
+//! The input is split into blocks and chained Merkle-Damgård style:
+//! ```text
+//! chain_0 = 0
+//! chain_i = x(Hash(BLOCK_TAG | chain_{i-1} | block_i))
+//! digest  = Hash(FINAL_TAG | chain_n | len(input) as u64)
+//! ```
+//! The tags separate block hashes from the finalization.
+//! The input length in the finalization disambiguates the zero padding
+//! of the last block.
+use crate::{
+	param::{PERDERSON_WINDOW_NUM, PERDERSON_WINDOW_SIZE},
+	*,
+};
+use ark_crypto_primitives::{FixedLengthCRH, FixedLengthCRHGadget};
+use ark_ed_on_bls12_381::Fq;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::SynthesisError;
+use ark_serialize::CanonicalSerialize;
+use ark_std::{vec, vec::Vec};
+use manta_error::MantaError;
+
+/// The domain separation tag of an input block.
+const VAR_LENGTH_HASH_BLOCK_TAG: u8 = 1;
+/// The domain separation tag of the finalization.
+const VAR_LENGTH_HASH_FINAL_TAG: u8 = 2;
+/// The number of bytes of the chaining value, i.e., a serialized `Fq`.
+const VAR_LENGTH_HASH_CHAIN_BYTES: usize = 32;
+/// The number of input bytes that are absorbed by each `Hash` evaluation.
+pub const VAR_LENGTH_HASH_BLOCK_BYTES: usize =
+	PERDERSON_WINDOW_SIZE * PERDERSON_WINDOW_NUM / 8 - 1 - VAR_LENGTH_HASH_CHAIN_BYTES;
+
+pub trait VariableLengthHash {
+	type Param;
+	type Output;
+
+	/// hash an input of arbitrary length
+	fn var_length_hash(param: &Self::Param, input: &[u8]) -> Result<Self::Output, MantaError>;
+}
+
+impl VariableLengthHash for MantaCrypto {
+	type Param = HashParam;
+	type Output = [u8; 32];
+
+	/// Hash an input of arbitrary length, and output the serialized digest.
+	fn var_length_hash(param: &Self::Param, input: &[u8]) -> Result<Self::Output, MantaError> {
+		let digest = var_length_hash(param, input)?;
+		let mut bytes = [0u8; 32];
+		digest.serialize(bytes.as_mut())?;
+		Ok(bytes)
+	}
+}
+
+/// Hash an input of arbitrary length with the Pedersen `Hash`.
+pub fn var_length_hash(param: &HashParam, input: &[u8]) -> Result<HashOutput, MantaError> {
+	let mut chain = [0u8; VAR_LENGTH_HASH_CHAIN_BYTES];
+	for block in input.chunks(VAR_LENGTH_HASH_BLOCK_BYTES) {
+		let digest = Hash::evaluate(
+			param,
+			&[[VAR_LENGTH_HASH_BLOCK_TAG].as_ref(), chain.as_ref(), block].concat(),
+		)?;
+		digest.x.serialize(chain.as_mut())?;
+	}
+
+	let len = (input.len() as u64).to_le_bytes();
+	let digest = Hash::evaluate(
+		param,
+		&[
+			[VAR_LENGTH_HASH_FINAL_TAG].as_ref(),
+			chain.as_ref(),
+			len.as_ref(),
+		]
+		.concat(),
+	)?;
+	Ok(digest)
+}
+
+/// Gadget for `var_length_hash`.
+/// The length of the input is fixed by the circuit.
+pub fn var_length_hash_gadget(
+	param: &HashParamVar,
+	input: &[UInt8<Fq>],
+) -> Result<HashOutputVar, SynthesisError> {
+	let mut chain = UInt8::constant_vec(&[0u8; VAR_LENGTH_HASH_CHAIN_BYTES]);
+	for block in input.chunks(VAR_LENGTH_HASH_BLOCK_BYTES) {
+		let mut bytes = vec![UInt8::constant(VAR_LENGTH_HASH_BLOCK_TAG)];
+		bytes.extend_from_slice(&chain);
+		bytes.extend_from_slice(block);
+		chain = HashVar::evaluate(param, &bytes)?.x.to_bytes()?;
+	}
+
+	let mut bytes: Vec<UInt8<Fq>> = vec![UInt8::constant(VAR_LENGTH_HASH_FINAL_TAG)];
+	bytes.extend_from_slice(&chain);
+	bytes.extend_from_slice(&UInt8::constant_vec(&(input.len() as u64).to_le_bytes()));
+	HashVar::evaluate(param, &bytes)
+}
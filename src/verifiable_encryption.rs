@@ -0,0 +1,207 @@
+// Copyright 2019-2021 Manta Network.
+// This file is part of manta-crypto.
+//
+// manta-crypto is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// manta-crypto is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with manta-crypto.  If not, see <http://www.gnu.org/licenses/>.
+
+//! This file implements a Diffie-Hellman based encryption of values over
+//! the JubJub curve, together with its gadget, so that a transfer circuit
+//! can prove that a ciphertext encrypts the committed value.
+//!
+//! The value is masked with a Poseidon hash of the shared secret:
+//! ```text
+//! ephemeral_pk = r * G
+//! masked_value = value + Poseidon(x(r * pk))
+//! ```
+//! The ciphertext is not authenticated; its integrity comes from the proof.
+use crate::{
+	poseidon::{hash_elements, hash_elements_var},
+	*,
+};
+use ark_ec::{AffineCurve, ProjectiveCurve};
+use ark_ed_on_bls12_381::{constraints::EdwardsVar, EdwardsAffine, EdwardsProjective, Fq, Fr};
+use ark_ff::PrimeField;
+use ark_r1cs_std::{bits::uint64::UInt64, fields::fp::FpVar, prelude::*};
+use ark_relations::r1cs::{Namespace, SynthesisError};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use ark_std::{
+	borrow::Borrow,
+	rand::{CryptoRng, RngCore},
+	UniformRand,
+};
+
+/// A value encrypted over the JubJub curve.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, CanonicalSerialize, CanonicalDeserialize)]
+pub struct JubjubCiphertext {
+	/// the ephemeral public key `r * G`
+	pub ephemeral_pk: EdwardsAffine,
+	/// the value, masked with the hash of the shared secret
+	pub masked_value: Fq,
+}
+
+pub trait VerifiableEncryption {
+	type Param;
+	type PublicKey;
+	type PrivateKey;
+	type Randomness;
+	type Ciphertext;
+	type Error;
+
+	/// Genrate a pair of keys
+	fn keygen<R: RngCore + CryptoRng>(rng: &mut R) -> (Self::PublicKey, Self::PrivateKey);
+
+	/// Encrypt the value under the input public key with some given randomness.
+	fn encrypt(
+		param: &Self::Param,
+		pk: &Self::PublicKey,
+		value: u64,
+		randomness: &Self::Randomness,
+	) -> Self::Ciphertext;
+
+	/// Decrypt a ciphertext with a secret key.
+	fn decrypt(
+		param: &Self::Param,
+		sk: &Self::PrivateKey,
+		cipher: &Self::Ciphertext,
+	) -> Result<u64, Self::Error>;
+}
+
+impl VerifiableEncryption for MantaCrypto {
+	type Param = PoseidonHashParam;
+	type PublicKey = EdwardsAffine;
+	type PrivateKey = Fr;
+	type Randomness = Fr;
+	type Ciphertext = JubjubCiphertext;
+	type Error = EciesError;
+
+	/// Generate a pair of keys `(sk * G, sk)`.
+	fn keygen<R: RngCore + CryptoRng>(rng: &mut R) -> (Self::PublicKey, Self::PrivateKey) {
+		let sk = Fr::rand(rng);
+		let pk = EdwardsProjective::prime_subgroup_generator()
+			.mul(sk.into_repr())
+			.into_affine();
+		(pk, sk)
+	}
+
+	/// Encrypt the value under the input public key.
+	///
+	/// # <weight>
+	/// Steps:
+	///     1. compute the ephemeral public key ephemeral_pk = randomness * G
+	///     2. compute the shared secret ss = randomness * pk
+	///     3. compute masked_value = value + Poseidon(x(ss))
+	///     4. return (ephemeral_pk, masked_value)
+	/// # </weight>
+	fn encrypt(
+		param: &Self::Param,
+		pk: &Self::PublicKey,
+		value: u64,
+		randomness: &Self::Randomness,
+	) -> Self::Ciphertext {
+		let ephemeral_pk = EdwardsProjective::prime_subgroup_generator()
+			.mul(randomness.into_repr())
+			.into_affine();
+		let shared_secret = pk.mul(randomness.into_repr()).into_affine();
+
+		JubjubCiphertext {
+			ephemeral_pk,
+			masked_value: Fq::from(value) + hash_elements(param, &[shared_secret.x]),
+		}
+	}
+
+	/// Decrypt a ciphertext with a secret key.
+	///
+	/// # <weight>
+	/// Steps:
+	///     1. compute the shared secret ss = sk * ephemeral_pk
+	///     2. compute value = masked_value - Poseidon(x(ss))
+	///     3. return value, or fail if it does not fit in 64 bits
+	/// # </weight>
+	fn decrypt(
+		param: &Self::Param,
+		sk: &Self::PrivateKey,
+		cipher: &Self::Ciphertext,
+	) -> Result<u64, Self::Error> {
+		let shared_secret = cipher.ephemeral_pk.mul(sk.into_repr()).into_affine();
+		let value = (cipher.masked_value - hash_elements(param, &[shared_secret.x])).into_repr();
+
+		// a wrong key yields a random field element,
+		// which fits in 64 bits with negligible probability
+		if value.0[1..].iter().any(|limb| *limb != 0) {
+			return Err(EciesError::MalformedPlaintext);
+		}
+		Ok(value.0[0])
+	}
+}
+
+//=======================
+// gadgets
+//=======================
+
+/// Gadget for `JubjubCiphertext`.
+#[derive(Clone)]
+pub struct JubjubCiphertextVar {
+	pub ephemeral_pk: EdwardsVar,
+	pub masked_value: FpVar<Fq>,
+}
+
+impl AllocVar<JubjubCiphertext, Fq> for JubjubCiphertextVar {
+	fn new_variable<T: Borrow<JubjubCiphertext>>(
+		cs: impl Into<Namespace<Fq>>,
+		f: impl FnOnce() -> Result<T, SynthesisError>,
+		mode: AllocationMode,
+	) -> Result<Self, SynthesisError> {
+		let ns = cs.into();
+		let cs = ns.cs();
+		let cipher = f().map(|cipher| *cipher.borrow());
+		let ephemeral_pk = EdwardsVar::new_variable(
+			cs.clone(),
+			|| cipher.map(|cipher| cipher.ephemeral_pk),
+			mode,
+		)?;
+		let masked_value =
+			FpVar::new_variable(cs, || cipher.map(|cipher| cipher.masked_value), mode)?;
+		Ok(Self {
+			ephemeral_pk,
+			masked_value,
+		})
+	}
+}
+
+impl EqGadget<Fq> for JubjubCiphertextVar {
+	fn is_eq(&self, other: &Self) -> Result<Boolean<Fq>, SynthesisError> {
+		self.ephemeral_pk
+			.is_eq(&other.ephemeral_pk)?
+			.and(&self.masked_value.is_eq(&other.masked_value)?)
+	}
+}
+
+/// Gadget for `<MantaCrypto as VerifiableEncryption>::encrypt`.
+/// The randomness is given by its little-endian bits.
+pub fn verifiable_encryption_gadget(
+	param: &PoseidonHashParamVar,
+	pk: &EdwardsVar,
+	value: &UInt64<Fq>,
+	randomness: &[Boolean<Fq>],
+) -> Result<JubjubCiphertextVar, SynthesisError> {
+	let ephemeral_pk = EdwardsVar::constant(EdwardsProjective::prime_subgroup_generator())
+		.scalar_mul_le(randomness.iter())?;
+	let shared_secret = pk.scalar_mul_le(randomness.iter())?;
+
+	let mask = hash_elements_var(&param.parameters, &[shared_secret.x])?;
+	let value = Boolean::le_bits_to_fp_var(&value.to_bits_le())?;
+	Ok(JubjubCiphertextVar {
+		ephemeral_pk,
+		masked_value: value + mask,
+	})
+}
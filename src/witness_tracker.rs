@@ -0,0 +1,276 @@
+// Copyright 2019-2021 Manta Network.
+// This file is part of manta-crypto.
+//
+// manta-crypto is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// manta-crypto is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with manta-crypto.  If not, see <http://www.gnu.org/licenses/>.
+
+//! This file implements a tracker of the membership paths of a wallet's own
+//! leaves, that follows the ledger tree append by append.
+//!
+//! The siblings of a marked leaf at index `i` are of two kinds:
+//! - at the levels where the bit of `i` is 1, the sibling is a complete
+//!   subtree on the left, which the frontier holds when the leaf is appended;
+//! - at the other levels, the sibling is a subtree on the right, which the
+//!   later leaves fill from the lowest level up. A witness keeps the roots
+//!   of the complete ones, and the frontier of the one being filled.
+//!
+//! So every append costs O(HEIGHT) hashes per marked leaf, and no witness
+//! ever needs the leaves of the tree.
+//! A leaf that is in the tree already, e.g., of a wallet that learned its
+//! path from a node, is marked from its current path: the right sibling
+//! that is being filled is the frontier of the tree below its level.
+use crate::{
+	incremental_merkle_tree::Frontier,
+	merkle_tree::{hash_leaf, MerkleTreeError, TreeDigest, TreeHashing},
+	IncrementalMerkleTree, MembershipPath,
+};
+use ark_crypto_primitives::FixedLengthCRH;
+use ark_ff::ToBytes;
+use ark_std::{collections::BTreeMap, vec, vec::Vec};
+
+/// A tree that keeps the membership paths of its marked leaves current.
+pub struct WitnessTracker<P: TreeHashing> {
+	tree: IncrementalMerkleTree<P>,
+	/// the witnesses of the marked leaves, by index
+	witnesses: BTreeMap<usize, Witness<P>>,
+}
+
+/// The data that a marked leaf needs for its path.
+struct Witness<P: TreeHashing> {
+	/// the frontier of the tree before the leaf, i.e., its left siblings
+	left: Vec<Option<TreeDigest<P>>>,
+	/// the roots of the complete right siblings, from the leaf up
+	filled: Vec<TreeDigest<P>>,
+	/// the level and the frontier of the right sibling that is being filled
+	cursor: Option<(usize, Frontier<P>)>,
+}
+
+impl<P: TreeHashing> Clone for WitnessTracker<P> {
+	fn clone(&self) -> Self {
+		Self {
+			tree: self.tree.clone(),
+			witnesses: self.witnesses.clone(),
+		}
+	}
+}
+
+impl<P: TreeHashing> Clone for Witness<P> {
+	fn clone(&self) -> Self {
+		Self {
+			left: self.left.clone(),
+			filled: self.filled.clone(),
+			cursor: self.cursor.clone(),
+		}
+	}
+}
+
+impl<P: TreeHashing> WitnessTracker<P> {
+	/// Track an empty tree.
+	pub fn new(param: <P::H as FixedLengthCRH>::Parameters) -> Result<Self, MerkleTreeError> {
+		Ok(Self::from_tree(IncrementalMerkleTree::new(param)?))
+	}
+
+	/// Track a tree from its current frontier, e.g., a wallet's checkpoint.
+	pub fn from_tree(tree: IncrementalMerkleTree<P>) -> Self {
+		Self {
+			tree,
+			witnesses: BTreeMap::new(),
+		}
+	}
+
+	/// The tracked tree.
+	pub fn tree(&self) -> &IncrementalMerkleTree<P> {
+		&self.tree
+	}
+
+	/// The indices of the marked leaves.
+	pub fn marked(&self) -> impl Iterator<Item = usize> + '_ {
+		self.witnesses.keys().copied()
+	}
+
+	/// Append a leaf, update the paths of the marked leaves, and return its index.
+	pub fn append<L: ToBytes>(&mut self, leaf: &L) -> Result<usize, MerkleTreeError> {
+		self.append_leaf(leaf, false)
+	}
+
+	/// Append a leaf, e.g., a wallet's own coin, and keep its path from now on.
+	pub fn append_marked<L: ToBytes>(&mut self, leaf: &L) -> Result<usize, MerkleTreeError> {
+		self.append_leaf(leaf, true)
+	}
+
+	/// Keep the path of a leaf that is in the tree already, e.g., a wallet's
+	/// coin whose path the wallet learned, and return whether `path` is the
+	/// current path of `leaf`; otherwise, the leaf is not marked.
+	///
+	/// # <weight>
+	/// Steps:
+	///     1. check the path against the root of the tree
+	///     2. for level in 0..k, with k the depth of the bottom tree:
+	///         a. if the bit level of index is 1, keep the sibling as a left sibling
+	///         b. otherwise, keep the sibling if it is a complete right sibling,
+	///            or else the frontier of the tree below level, if the right
+	///            sibling is being filled, and skip the right siblings above
+	/// # </weight>
+	pub fn mark<L: ToBytes>(
+		&mut self,
+		leaf: &L,
+		path: &MembershipPath<P>,
+	) -> Result<bool, MerkleTreeError> {
+		let index = path.index;
+		if index >= self.tree.len() {
+			return Err(MerkleTreeError::InvalidLeafIndex);
+		}
+		let depth = self.tree.bottom_depth();
+		if path.siblings.len() != depth + IncrementalMerkleTree::<P>::padding(depth)
+			|| !path.verify(&self.tree.param, &self.tree.root()?, leaf)?
+		{
+			return Ok(false);
+		}
+		let size = self.tree.len();
+		let mut witness = Witness {
+			left: vec![None; P::HEIGHT],
+			filled: Vec::new(),
+			cursor: None,
+		};
+		let mut complete = true;
+		for level in 0..depth {
+			if (index >> level) & 1 == 1 {
+				witness.left[level] = Some(path.siblings[level].clone());
+				continue;
+			}
+			if !complete {
+				continue;
+			}
+			// the right sibling holds the leaves from `start` on
+			let start = ((index >> level) | 1) << level;
+			if start + (1 << level) <= size {
+				witness.filled.push(path.siblings[level].clone());
+			} else {
+				if start < size {
+					// the frontier of the right sibling is the one of the tree below `level`
+					let mut frontier = Frontier::new();
+					frontier.size = size - start;
+					frontier.filled[..level].clone_from_slice(&self.tree.frontier.filled[..level]);
+					witness.cursor = Some((level, frontier));
+				}
+				complete = false;
+			}
+		}
+		self.witnesses.insert(index, witness);
+		Ok(true)
+	}
+
+	/// Stop to keep the path of a leaf, e.g., once it is spent,
+	/// and return whether it was marked.
+	pub fn unmark(&mut self, index: usize) -> bool {
+		self.witnesses.remove(&index).is_some()
+	}
+
+	/// The current path of a marked leaf.
+	///
+	/// # <weight>
+	/// Steps:
+	///     1. set k = the depth of the bottom tree
+	///     2. for level in 0..k:
+	///         a. if the bit level of index is 1, push the left sibling
+	///         b. otherwise, push the next right sibling: a complete one, or
+	///            the root of the one being filled, or an empty subtree
+	///     3. push the empty leaf as a sibling up to the height of the tree
+	/// # </weight>
+	pub fn path(&self, index: usize) -> Result<MembershipPath<P>, MerkleTreeError> {
+		let witness = self
+			.witnesses
+			.get(&index)
+			.ok_or(MerkleTreeError::InvalidLeafIndex)?;
+		let depth = self.tree.bottom_depth();
+		let mut siblings = Vec::with_capacity(P::HEIGHT);
+		let mut right = witness.filled.iter();
+		let mut cursor = witness.cursor.as_ref();
+		for level in 0..depth {
+			if (index >> level) & 1 == 1 {
+				siblings.push(
+					witness.left[level]
+						.clone()
+						.expect("a left sibling is complete before the leaf"),
+				);
+			} else if let Some(sibling) = right.next() {
+				siblings.push(sibling.clone());
+			} else if let Some((cursor_level, frontier)) = cursor.take() {
+				siblings.push(frontier.subtree_root(
+					&self.tree.param,
+					&self.tree.empty,
+					*cursor_level,
+				)?);
+			} else {
+				siblings.push(self.tree.empty[level].clone());
+			}
+		}
+		let padding = IncrementalMerkleTree::<P>::padding(depth);
+		siblings.extend(ark_std::iter::repeat(self.tree.empty[0].clone()).take(padding));
+		Ok(MembershipPath { index, siblings })
+	}
+
+	fn append_leaf<L: ToBytes>(&mut self, leaf: &L, mark: bool) -> Result<usize, MerkleTreeError> {
+		if self.tree.len() == IncrementalMerkleTree::<P>::CAPACITY {
+			return Err(MerkleTreeError::TreeFull);
+		}
+		let node = hash_leaf::<P, _>(&self.tree.param, leaf)?;
+		for (index, witness) in self.witnesses.iter_mut() {
+			witness.append(&self.tree.param, &self.tree.empty, *index, node.clone())?;
+		}
+		let index = self.tree.len();
+		if mark {
+			self.witnesses.insert(
+				index,
+				Witness {
+					left: self.tree.frontier.filled.clone(),
+					filled: Vec::new(),
+					cursor: None,
+				},
+			);
+		}
+		self.tree.frontier.append(&self.tree.param, node)?;
+		Ok(index)
+	}
+}
+
+impl<P: TreeHashing> Witness<P> {
+	/// Append the hash of a leaf to the right sibling that is being filled.
+	fn append(
+		&mut self,
+		param: &<P::H as FixedLengthCRH>::Parameters,
+		empty: &[TreeDigest<P>],
+		index: usize,
+		node: TreeDigest<P>,
+	) -> Result<(), MerkleTreeError> {
+		let (level, mut frontier) = match self.cursor.take() {
+			Some(cursor) => cursor,
+			// the next right sibling is at the next level where the bit of index is 0
+			None => {
+				let level = (0..P::HEIGHT)
+					.filter(|level| (index >> level) & 1 == 0)
+					.nth(self.filled.len())
+					.ok_or(MerkleTreeError::TreeFull)?;
+				(level, Frontier::new())
+			}
+		};
+		frontier.append(param, node)?;
+		if frontier.size == 1 << level {
+			self.filled
+				.push(frontier.subtree_root(param, empty, level)?);
+		} else {
+			self.cursor = Some((level, frontier));
+		}
+		Ok(())
+	}
+}